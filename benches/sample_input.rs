@@ -7,8 +7,8 @@ use http_monitor::{monitor_stream, Config};
 fn bench_monitor_sample_input(c: &mut Criterion) {
     c.bench_function("monitor sample output", |b| {
         b.iter(|| {
-            let input = black_box(&include_str!("../samples/input.csv")[..]);
-            let expected = black_box(&include_str!("../samples/output.txt")[..]);
+            let input = black_box(include_str!("../samples/input.csv"));
+            let expected = black_box(include_str!("../samples/output.txt"));
 
             let mut source = Cursor::new(input);
             let mut sink = Cursor::new(Vec::new());
@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    io::{self, Cursor, Read},
+};
+
+/// A compression format that input may be in, identified by its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, including multiple concatenated members.
+    Gzip,
+    /// Zstandard.
+    Zstd,
+    /// bzip2, including multiple concatenated streams.
+    Bzip2,
+}
+
+impl Compression {
+    /// The longest magic byte prefix that we need to look at to detect a format.
+    const MAGIC_LENGTH: usize = 4;
+
+    /// Identifies the compression format from the first bytes of input, if it's compressed.
+    pub fn detect(prefix: &[u8]) -> Option<Compression> {
+        if prefix.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if prefix.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if prefix.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        })
+    }
+}
+
+/// Wraps source to decompress it on the fly if it starts with the magic bytes of a supported
+/// compression format, or otherwise returns its contents unchanged.
+pub fn decompress<'a>(mut source: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    // A single read may return fewer bytes than we need, particularly from a pipe.
+    let mut prefix = Vec::with_capacity(Compression::MAGIC_LENGTH);
+    (&mut source)
+        .take(Compression::MAGIC_LENGTH as u64)
+        .read_to_end(&mut prefix)?;
+
+    let compression = Compression::detect(&prefix);
+    let source = Cursor::new(prefix).chain(source);
+
+    Ok(match compression {
+        None => Box::new(source),
+        Some(compression) => {
+            log::info!("decompressing {} input", compression);
+            match compression {
+                Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(source)),
+                Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(source)?),
+                Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(source)),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const INPUT: &str = include_str!("../samples/input.csv");
+
+    fn decompressed(compressed: &[u8]) -> String {
+        let mut output = String::new();
+        decompress(compressed)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_uncompressed() {
+        assert_eq!(decompressed(INPUT.as_bytes()), INPUT);
+        assert_eq!(decompressed(b"a"), "a");
+        assert_eq!(decompressed(b""), "");
+    }
+
+    #[test]
+    fn test_gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(INPUT.as_bytes()).unwrap();
+        assert_eq!(decompressed(&encoder.finish().unwrap()), INPUT);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let compressed = zstd::stream::encode_all(INPUT.as_bytes(), 0).unwrap();
+        assert_eq!(decompressed(&compressed), INPUT);
+    }
+
+    #[test]
+    fn test_bzip2() {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        encoder.write_all(INPUT.as_bytes()).unwrap();
+        assert_eq!(decompressed(&encoder.finish().unwrap()), INPUT);
+    }
+}
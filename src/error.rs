@@ -0,0 +1,107 @@
+use std::{error::Error as StdError, fmt, io, path::PathBuf};
+
+use crate::{ConfigError, LateRecordError, ParseError};
+
+/// An error from reading request records or running monitors over them.
+///
+/// Errors from bad input, such as [Error::Header] and [Error::Parse], can be told apart from
+/// failures of the environment, such as [Error::Io], and of monitors.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The config is invalid.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// A config file couldn't be read or deserialized.
+    #[error("invalid config file {}: {reason}", .path.display())]
+    ConfigFile {
+        /// The path of the config file.
+        path: PathBuf,
+        /// What's wrong with it, naming the key that couldn't be loaded if there is one.
+        reason: String,
+    },
+    /// The input format wasn't specified, and couldn't be detected.
+    #[error("unable to detect the input format from the first {lines} lines, please specify one")]
+    UndetectedFormat {
+        /// The number of lines that detection looked at.
+        lines: usize,
+    },
+    /// The CSV header row doesn't match the configured columns.
+    #[error("invalid csv header: {0}")]
+    Header(String),
+    /// A record couldn't be parsed.
+    #[error("invalid record at {position}")]
+    Parse {
+        /// Where the record is in the input.
+        position: Position,
+        /// Why it couldn't be parsed.
+        #[source]
+        source: ParseError,
+    },
+    /// A record was too far out of order to be sorted, under [crate::LatePolicy::Fail].
+    #[error(transparent)]
+    Late(#[from] LateRecordError),
+    /// One of several named inputs failed.
+    #[error("failed to read input {name}")]
+    Input {
+        /// The name of the input.
+        name: String,
+        /// How it failed.
+        #[source]
+        source: Box<Error>,
+    },
+    /// A record that couldn't be parsed couldn't be written to the dead letter file, under
+    /// [crate::ErrorPolicy::DeadLetter].
+    #[error("failed to write rejected record to {}", .path.display())]
+    DeadLetter {
+        /// The path of the dead letter file.
+        path: PathBuf,
+        /// Why the write failed.
+        #[source]
+        source: io::Error,
+    },
+    /// Reading input or writing output failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Serving metrics or connecting to an exporter failed.
+    #[error("failed to set up {name}")]
+    Setup {
+        /// What was being set up, such as "metrics server on 127.0.0.1:9184".
+        name: String,
+        /// How it failed.
+        #[source]
+        source: io::Error,
+    },
+    /// A monitor failed.
+    #[error("monitor failed")]
+    Monitor(#[source] Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    /// Whether this error was caused by invalid input, rather than by the config, the
+    /// environment or a monitor.
+    pub fn is_bad_input(&self) -> bool {
+        match self {
+            Error::UndetectedFormat { .. }
+            | Error::Header(_)
+            | Error::Parse { .. }
+            | Error::Late(LateRecordError::Late { .. }) => true,
+            Error::Input { source, .. } => source.is_bad_input(),
+            _ => false,
+        }
+    }
+}
+
+/// The position of a record in its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// The one-based line number that the record starts on.
+    pub line: u64,
+    /// The zero-based offset of the byte that the record starts at, after decompression.
+    pub offset: u64,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, byte {}", self.line, self.offset)
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{metric_name_component, Exporter, TcpConnection};
+use crate::StatsWindow;
+
+/// Configuration for exporting stats with Graphite's plaintext protocol.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GraphiteConfig {
+    /// The host and port of the Graphite (Carbon) server, such as `127.0.0.1:2003`.
+    pub address: String,
+    /// The prefix for each metric's path.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+fn default_prefix() -> String {
+    "http_monitor".to_string()
+}
+
+impl GraphiteConfig {
+    /// Configures exporting to address, with the default prefix.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            prefix: default_prefix(),
+        }
+    }
+}
+
+/// Sends the stats for each window to Graphite over TCP, as the number of requests in total,
+/// by section and by status code, and the request rate, timestamped with the window's start.
+#[derive(Debug)]
+pub struct GraphiteExporter {
+    connection: TcpConnection,
+    prefix: String,
+}
+
+impl GraphiteExporter {
+    /// Creates an exporter, which connects to the configured address when it's first used.
+    pub fn new(config: &GraphiteConfig) -> Self {
+        Self {
+            connection: TcpConnection::new(&config.address),
+            prefix: config.prefix.clone(),
+        }
+    }
+
+    /// Formats the stats as Graphite plaintext protocol lines.
+    fn lines(&self, stats: &StatsWindow) -> Vec<String> {
+        let prefix = &self.prefix;
+        let time = stats.start;
+        let mut lines = vec![
+            format!("{}.requests {} {}", prefix, stats.request_count, time),
+            format!("{}.request_rate {} {}", prefix, stats.request_rate, time),
+        ];
+        for (section, count) in &stats.sections {
+            lines.push(format!(
+                "{}.sections.{}.requests {} {}",
+                prefix,
+                metric_name_component(section),
+                count,
+                time
+            ));
+        }
+        for (status, count) in &stats.status_codes {
+            lines.push(format!(
+                "{}.status.{}.requests {} {}",
+                prefix, status, count, time
+            ));
+        }
+        lines
+    }
+}
+
+impl Exporter for GraphiteExporter {
+    fn name(&self) -> &'static str {
+        "Graphite"
+    }
+
+    fn export(&mut self, stats: &StatsWindow) -> io::Result<()> {
+        let lines = self.lines(stats);
+        self.connection.send_lines(&lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::{Ipv4Addr, TcpListener},
+    };
+
+    use super::super::tests::stats;
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut exporter = GraphiteExporter::new(&GraphiteConfig::new(&address));
+
+        exporter.export(&stats()).unwrap();
+        exporter.export(&stats()).unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<String> = BufReader::new(stream)
+            .lines()
+            .take(12)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lines[..6],
+            [
+                "http_monitor.requests 3 1549573860",
+                "http_monitor.request_rate 0.3 1549573860",
+                "http_monitor.sections.api.requests 2 1549573860",
+                "http_monitor.sections.root.requests 1 1549573860",
+                "http_monitor.status.200.requests 2 1549573860",
+                "http_monitor.status.500.requests 1 1549573860",
+            ]
+        );
+        // Both windows are sent over the same connection.
+        assert_eq!(lines[..6], lines[6..]);
+    }
+}
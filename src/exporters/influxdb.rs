@@ -0,0 +1,152 @@
+use std::{io, net::UdpSocket};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{send_datagrams, udp_socket, Exporter, TcpConnection, Transport};
+use crate::StatsWindow;
+
+/// Configuration for exporting stats with InfluxDB's line protocol, such as to InfluxDB's UDP
+/// listener or Telegraf's socket listener.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbConfig {
+    /// The host and port to send to, such as `127.0.0.1:8089`.
+    pub address: String,
+    /// Whether to send over UDP or TCP.
+    #[serde(default = "default_transport")]
+    pub transport: Transport,
+    /// The name of the measurement for the totals, which is also the prefix of the
+    /// measurements for the breakdowns by section and by status.
+    #[serde(default = "default_measurement")]
+    pub measurement: String,
+}
+
+fn default_transport() -> Transport {
+    Transport::Udp
+}
+
+fn default_measurement() -> String {
+    "http_monitor".to_string()
+}
+
+impl InfluxDbConfig {
+    /// Configures exporting to address over UDP, with the default measurement name.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            transport: default_transport(),
+            measurement: default_measurement(),
+        }
+    }
+}
+
+/// Sends the stats for each window as InfluxDB line protocol, as points with the number of
+/// requests in total, by section and by status code, and the request rate, timestamped with
+/// the window's start.
+#[derive(Debug)]
+pub struct InfluxDbExporter {
+    connection: Connection,
+    measurement: String,
+}
+
+#[derive(Debug)]
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpConnection),
+}
+
+impl InfluxDbExporter {
+    /// Creates an exporter for the configured address. TCP connections are opened when the
+    /// exporter is first used.
+    pub fn new(config: &InfluxDbConfig) -> io::Result<Self> {
+        Ok(Self {
+            connection: match config.transport {
+                Transport::Udp => Connection::Udp(udp_socket(&config.address)?),
+                Transport::Tcp => Connection::Tcp(TcpConnection::new(&config.address)),
+            },
+            measurement: config.measurement.clone(),
+        })
+    }
+
+    /// Formats the stats as InfluxDB line protocol.
+    fn lines(&self, stats: &StatsWindow) -> Vec<String> {
+        let measurement = escape(&self.measurement, ", ");
+        // Timestamps are in nanoseconds by default.
+        let time = u64::from(stats.start) * 1_000_000_000;
+
+        let mut lines = vec![format!(
+            "{} requests={}i,request_rate={} {}",
+            measurement, stats.request_count, stats.request_rate, time
+        )];
+        for (section, count) in &stats.sections {
+            lines.push(format!(
+                "{}_by_section,section={} requests={}i {}",
+                measurement,
+                escape(section, ",= "),
+                count,
+                time
+            ));
+        }
+        for (status, count) in &stats.status_codes {
+            lines.push(format!(
+                "{}_by_status,status={} requests={}i {}",
+                measurement, status, count, time
+            ));
+        }
+        lines
+    }
+}
+
+/// Escapes the special characters in a measurement name or tag value with backslashes.
+fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Exporter for InfluxDbExporter {
+    fn name(&self) -> &'static str {
+        "InfluxDB"
+    }
+
+    fn export(&mut self, stats: &StatsWindow) -> io::Result<()> {
+        let lines = self.lines(stats);
+        match &mut self.connection {
+            Connection::Udp(socket) => send_datagrams(socket, &lines),
+            Connection::Tcp(connection) => connection.send_lines(&lines),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{receive_lines, stats, udp_listener};
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let (listener, address) = udp_listener();
+        let mut exporter = InfluxDbExporter::new(&InfluxDbConfig::new(&address)).unwrap();
+
+        let mut stats = stats();
+        stats.sections.push(("/a b,c".to_string(), 1));
+        exporter.export(&stats).unwrap();
+
+        assert_eq!(
+            receive_lines(&listener),
+            vec![
+                "http_monitor requests=3i,request_rate=0.3 1549573860000000000",
+                "http_monitor_by_section,section=/api requests=2i 1549573860000000000",
+                "http_monitor_by_section,section=/ requests=1i 1549573860000000000",
+                r"http_monitor_by_section,section=/a\ b\,c requests=1i 1549573860000000000",
+                "http_monitor_by_status,status=200 requests=2i 1549573860000000000",
+                "http_monitor_by_status,status=500 requests=1i 1549573860000000000",
+            ]
+        );
+    }
+}
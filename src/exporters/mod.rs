@@ -0,0 +1,381 @@
+//! Exporters that push stats for each window to external metrics systems.
+
+mod graphite;
+mod influxdb;
+mod statsd;
+
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+pub use self::graphite::{GraphiteConfig, GraphiteExporter};
+pub use self::influxdb::{InfluxDbConfig, InfluxDbExporter};
+pub use self::statsd::{StatsdConfig, StatsdExporter};
+use crate::StatsWindow;
+
+/// Pushes the stats for each window to an external metrics system.
+pub trait Exporter: Debug {
+    /// The name of the metrics system, for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Sends the stats for a window that's just ended.
+    fn export(&mut self, stats: &StatsWindow) -> io::Result<()>;
+}
+
+/// The exporters to push stats to, each of which is disabled if it isn't configured.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Exporters {
+    /// Sends StatsD packets over UDP.
+    pub statsd: Option<StatsdConfig>,
+    /// Sends Graphite plaintext protocol over TCP.
+    pub graphite: Option<GraphiteConfig>,
+    /// Sends InfluxDB line protocol over UDP or TCP.
+    pub influxdb: Option<InfluxDbConfig>,
+}
+
+impl Exporters {
+    /// Creates each of the configured exporters.
+    pub fn create(&self) -> io::Result<Vec<Box<dyn Exporter>>> {
+        let mut exporters: Vec<Box<dyn Exporter>> = Vec::new();
+        if let Some(config) = &self.statsd {
+            exporters.push(Box::new(StatsdExporter::new(config)?));
+        }
+        if let Some(config) = &self.graphite {
+            exporters.push(Box::new(GraphiteExporter::new(config)));
+        }
+        if let Some(config) = &self.influxdb {
+            exporters.push(Box::new(InfluxDbExporter::new(config)?));
+        }
+        Ok(exporters)
+    }
+}
+
+/// A network protocol to send metrics over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    /// One or more datagrams per window.
+    Udp,
+    /// A persistent connection, which is reconnected if it fails.
+    Tcp,
+}
+
+/// Creates a UDP socket that sends to address.
+fn udp_socket(address: &str) -> io::Result<UdpSocket> {
+    let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} didn't resolve to any address", address),
+        )
+    })?;
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.connect(target)?;
+    Ok(socket)
+}
+
+/// Sends lines as UDP datagrams, packing as many whole lines into each as will fit in a
+/// typical network MTU.
+fn send_datagrams(socket: &UdpSocket, lines: &[String]) -> io::Result<()> {
+    // Leaves room for IP and UDP headers within a 1500 byte Ethernet MTU.
+    const MAX_DATAGRAM_LENGTH: usize = 1432;
+
+    let mut datagram = String::new();
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_LENGTH {
+            socket.send(datagram.as_bytes())?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(line);
+    }
+    if !datagram.is_empty() {
+        socket.send(datagram.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// A TCP connection that's opened when it's first needed, and reopened if it fails.
+#[derive(Debug)]
+struct TcpConnection {
+    address: String,
+    stream: Option<TcpStream>,
+    /// How long to wait after the last failed attempt to connect before trying again, which
+    /// doubles with each failure so that a server that's down doesn't cost a connect timeout
+    /// every window.
+    reconnect_backoff: Duration,
+    /// When to next try to connect, if the last attempt failed.
+    reconnect_at: Option<Instant>,
+}
+
+impl TcpConnection {
+    fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            stream: None,
+            reconnect_backoff: Duration::ZERO,
+            reconnect_at: None,
+        }
+    }
+
+    /// Writes each of lines, terminated by a newline, reconnecting and retrying once if the
+    /// existing connection has failed before any of them were written. Once some have been
+    /// written, retrying could duplicate them, so the rest are dropped instead.
+    fn send_lines(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut data = lines.join("\n");
+        data.push('\n');
+
+        if let Some(stream) = &mut self.stream {
+            match write_all_counted(stream, data.as_bytes()) {
+                Ok(()) => return Ok(()),
+                Err((0, _)) => {
+                    log::debug!("connection to {} failed, reconnecting", self.address)
+                }
+                Err((_, error)) => {
+                    self.stream = None;
+                    return Err(error);
+                }
+            }
+        }
+
+        self.stream = None;
+        if self.reconnect_at.is_some_and(|at| Instant::now() < at) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!(
+                    "waiting up to {:?} to reconnect to {}",
+                    self.reconnect_backoff, self.address
+                ),
+            ));
+        }
+        let mut stream = match self.connect() {
+            Ok(stream) => {
+                self.reconnect_backoff = Duration::ZERO;
+                self.reconnect_at = None;
+                stream
+            }
+            Err(error) => {
+                self.reconnect_backoff = (self.reconnect_backoff * 2)
+                    .clamp(MIN_RECONNECT_BACKOFF, MAX_RECONNECT_BACKOFF);
+                self.reconnect_at = Some(Instant::now() + self.reconnect_backoff);
+                return Err(error);
+            }
+        };
+        write_all_counted(&mut stream, data.as_bytes()).map_err(|(_, error)| error)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Connects to the first of the address's resolved addresses that accepts, with timeouts
+    /// so that an unreachable server can't hold up the monitors for long.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, TCP_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} didn't resolve to any address", self.address),
+            )
+        }))
+    }
+}
+
+/// How long to wait to connect or write to a TCP server, since exporting happens on the same
+/// thread as monitoring.
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The range of how long to wait before reconnecting to a TCP server after failing to.
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Writes all of data like [Write::write_all], but on failure also returns how many bytes were
+/// written first.
+fn write_all_counted(stream: &mut impl Write, mut data: &[u8]) -> Result<(), (usize, io::Error)> {
+    let length = data.len();
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => {
+                let error =
+                    io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer");
+                return Err((length - data.len(), error));
+            }
+            Ok(written) => data = &data[written..],
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err((length - data.len(), error)),
+        }
+    }
+    Ok(())
+}
+
+/// Converts a section like "/api" into a metric path component like "api", replacing any
+/// characters that have special meaning in dotted metric names.
+fn metric_name_component(section: &str) -> String {
+    let name: String = section
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Stats for a window with a couple of sections and status codes.
+    pub(super) fn stats() -> StatsWindow {
+        StatsWindow {
+            start: 1549573860,
+            end: 1549573870,
+            request_count: 3,
+            request_rate: 0.3,
+            malformed_count: 0,
+            sections: vec![("/api".to_string(), 2), ("/".to_string(), 1)],
+            status_codes: vec![(200, 2), (500, 1)],
+            query_sections: vec![],
+            methods: vec![("GET".into(), 3)],
+            protocols: vec![("HTTP/1.0".into(), 3)],
+            user_agents: vec![],
+            referers: vec![],
+            sources: vec![],
+        }
+    }
+
+    /// Binds a UDP socket on localhost to receive an exporter's datagrams.
+    pub(super) fn udp_listener() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        (socket, address)
+    }
+
+    /// Receives a datagram from socket, as lines.
+    pub(super) fn receive_lines(socket: &UdpSocket) -> Vec<String> {
+        let mut buffer = vec![0; 65_535];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length])
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_metric_name_component() {
+        assert_eq!(metric_name_component("/api"), "api");
+        assert_eq!(metric_name_component("/"), "root");
+        assert_eq!(metric_name_component("/a.b:c"), "a_b_c");
+    }
+
+    /// A writer that accepts a few bytes, then fails.
+    struct FailingWriter(usize);
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let written = self.0.min(buf.len());
+            self.0 -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_counted() {
+        assert!(write_all_counted(&mut FailingWriter(10), b"abc").is_ok());
+        assert_eq!(
+            write_all_counted(&mut FailingWriter(0), b"abc")
+                .unwrap_err()
+                .0,
+            0
+        );
+        assert_eq!(
+            write_all_counted(&mut FailingWriter(2), b"abc")
+                .unwrap_err()
+                .0,
+            2
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        // Nothing's listening on the port once the listener's dropped.
+        let address = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let mut connection = TcpConnection::new(&address);
+        let lines = vec!["line".to_string()];
+
+        let error = connection.send_lines(&lines).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(connection.reconnect_backoff, MIN_RECONNECT_BACKOFF);
+
+        // It doesn't try again until the backoff has passed.
+        let error = connection.send_lines(&lines).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+
+        connection.reconnect_at = Some(Instant::now());
+        let error = connection.send_lines(&lines).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(connection.reconnect_backoff, MIN_RECONNECT_BACKOFF * 2);
+    }
+
+    #[test]
+    fn test_send_datagrams_splits_lines() {
+        let (listener, address) = udp_listener();
+        let socket = udp_socket(&address).unwrap();
+
+        let lines: Vec<String> = (0..100).map(|i| format!("{:030}", i)).collect();
+        send_datagrams(&socket, &lines).unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < lines.len() {
+            let datagram = receive_lines(&listener);
+            assert!(datagram.len() < lines.len());
+            received.extend(datagram);
+        }
+        assert_eq!(received, lines);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{io, net::UdpSocket};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{metric_name_component, send_datagrams, udp_socket, Exporter};
+use crate::StatsWindow;
+
+/// Configuration for exporting stats as StatsD metrics.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StatsdConfig {
+    /// The host and port of the StatsD server, such as `127.0.0.1:8125`.
+    pub address: String,
+    /// The prefix for each metric's name.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+fn default_prefix() -> String {
+    "http_monitor".to_string()
+}
+
+impl StatsdConfig {
+    /// Configures exporting to address, with the default prefix.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            prefix: default_prefix(),
+        }
+    }
+}
+
+/// Sends the stats for each window to StatsD over UDP, as counters of requests in total, by
+/// section and by status code, and a gauge of the request rate.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdExporter {
+    /// Creates a socket to send to the configured address.
+    pub fn new(config: &StatsdConfig) -> io::Result<Self> {
+        Ok(Self {
+            socket: udp_socket(&config.address)?,
+            prefix: config.prefix.clone(),
+        })
+    }
+
+    /// Formats the stats as StatsD metric lines.
+    fn lines(&self, stats: &StatsWindow) -> Vec<String> {
+        let prefix = &self.prefix;
+        let mut lines = vec![
+            format!("{}.requests:{}|c", prefix, stats.request_count),
+            format!("{}.request_rate:{}|g", prefix, stats.request_rate),
+        ];
+        for (section, count) in &stats.sections {
+            lines.push(format!(
+                "{}.sections.{}.requests:{}|c",
+                prefix,
+                metric_name_component(section),
+                count
+            ));
+        }
+        for (status, count) in &stats.status_codes {
+            lines.push(format!("{}.status.{}.requests:{}|c", prefix, status, count));
+        }
+        lines
+    }
+}
+
+impl Exporter for StatsdExporter {
+    fn name(&self) -> &'static str {
+        "StatsD"
+    }
+
+    fn export(&mut self, stats: &StatsWindow) -> io::Result<()> {
+        send_datagrams(&self.socket, &self.lines(stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{receive_lines, stats, udp_listener};
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let (listener, address) = udp_listener();
+        let mut exporter = StatsdExporter::new(&StatsdConfig::new(&address)).unwrap();
+
+        exporter.export(&stats()).unwrap();
+
+        assert_eq!(
+            receive_lines(&listener),
+            vec![
+                "http_monitor.requests:3|c",
+                "http_monitor.request_rate:0.3|g",
+                "http_monitor.sections.api.requests:2|c",
+                "http_monitor.sections.root.requests:1|c",
+                "http_monitor.status.200.requests:2|c",
+                "http_monitor.status.500.requests:1|c",
+            ]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Reads a file like `tail -F`, from the start and then indefinitely as it's appended to.
+///
+/// When the reader reaches the end of the file, it waits for more data to be written. If the
+/// file at the path is replaced (such as by log rotation) the reader finishes the old file and
+/// then continues from the start of the new one. If the file is truncated in place (such as by
+/// `copytruncate` rotation) the reader continues from its new start. Reads never return EOF.
+#[derive(Debug)]
+pub struct FollowReader {
+    /// The path being followed.
+    path: PathBuf,
+    /// The file currently open from that path.
+    file: File,
+    /// The identity of the open file, if the platform lets us detect when it's replaced.
+    file_id: Option<FileId>,
+    /// The number of bytes read from the open file.
+    position: u64,
+    /// Whether the last byte read was a newline, or if nothing has been read yet.
+    at_line_start: bool,
+    /// A newline that needs to be returned before anything else, to terminate a partial line
+    /// left at the end of a rotated or truncated file.
+    pending_newline: bool,
+    /// How long to wait before checking for changes after reaching the end of the file.
+    poll_interval: Duration,
+}
+
+/// The device and inode numbers identifying a file.
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<FileId> {
+    None
+}
+
+impl FollowReader {
+    /// The default value for how long to wait before checking for changes to the file.
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Opens the file at path to be followed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let file_id = file_id(&file.metadata()?);
+
+        Ok(Self {
+            path,
+            file,
+            file_id,
+            position: 0,
+            at_line_start: true,
+            pending_newline: false,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+        })
+    }
+
+    /// Sets how long to wait before checking for changes after reaching the end of the file.
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Self {
+        Self {
+            poll_interval,
+            ..self
+        }
+    }
+
+    /// Called when we've reached the end of the open file, to check whether it's been rotated
+    /// or truncated, switching to the new data if so. Returns whether there may be more to read.
+    fn check_for_changes(&mut self) -> io::Result<bool> {
+        let current = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // The old file may have been moved before a new one has been created.
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+
+        let replaced = match (file_id(&current), self.file_id) {
+            (Some(current), Some(open)) => current != open,
+            _ => false,
+        };
+
+        let open_length = self.file.metadata()?.len();
+
+        if replaced && open_length > self.position {
+            // The old file was appended to before it was replaced, so finish reading it first.
+            return Ok(true);
+        } else if replaced {
+            log::info!("{} was replaced, reopening it", self.path.display());
+            self.file = File::open(&self.path)?;
+            self.file_id = file_id(&self.file.metadata()?);
+        } else if open_length < self.position {
+            log::info!(
+                "{} was truncated, reading from its start",
+                self.path.display()
+            );
+            self.file.seek(SeekFrom::Start(0))?;
+        } else {
+            return Ok(false);
+        }
+
+        self.position = 0;
+        self.pending_newline = !self.at_line_start;
+        Ok(true)
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.pending_newline {
+                self.pending_newline = false;
+                self.at_line_start = true;
+                buf[0] = b'\n';
+                return Ok(1);
+            }
+
+            let length = self.file.read(buf)?;
+            if length > 0 {
+                self.position += length as u64;
+                self.at_line_start = buf[length - 1] == b'\n';
+                return Ok(length);
+            }
+
+            if !self.check_for_changes()? {
+                thread::sleep(self.poll_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    /// Creates an empty directory for a test's files.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("http-monitor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Follows path on a background thread, returning a channel of the lines read.
+    fn follow_lines(path: &Path) -> mpsc::Receiver<String> {
+        let reader = FollowReader::open(path)
+            .unwrap()
+            .with_poll_interval(Duration::from_millis(5));
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                if sender.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    fn next_lines(receiver: &mpsc::Receiver<String>, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect()
+    }
+
+    fn append(path: &Path, content: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follow_appends_and_rotation() {
+        let dir = test_dir("follow-rotation");
+        let path = dir.join("access.log");
+        append(&path, "one\ntwo\n");
+
+        let lines = follow_lines(&path);
+        assert_eq!(next_lines(&lines, 2), vec!["one", "two"]);
+
+        append(&path, "thr");
+        append(&path, "ee\n");
+        assert_eq!(next_lines(&lines, 1), vec!["three"]);
+
+        // The partial line left at the end of the rotated file is terminated, so it isn't
+        // merged with the first line of the new file.
+        append(&path, "four\nfive");
+        fs::rename(&path, dir.join("access.log.1")).unwrap();
+        append(&path, "six\n");
+        assert_eq!(next_lines(&lines, 3), vec!["four", "five", "six"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_truncation() {
+        let dir = test_dir("follow-truncation");
+        let path = dir.join("access.log");
+        append(&path, "one\ntwo\n");
+
+        let lines = follow_lines(&path);
+        assert_eq!(next_lines(&lines, 2), vec!["one", "two"]);
+
+        File::create(&path).unwrap();
+        // Give the reader a chance to notice the truncation before the file grows again.
+        thread::sleep(Duration::from_millis(50));
+        append(&path, "three\n");
+        assert_eq!(next_lines(&lines, 1), vec!["three"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
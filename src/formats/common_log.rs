@@ -0,0 +1,275 @@
+use std::{convert::TryInto, io::BufRead};
+
+use chrono::DateTime;
+use string_cache::DefaultAtom;
+
+use super::{next_line_record, parse_field, ParseError, PositionedLines};
+use crate::{Error, RequestLine, RequestRecord};
+
+/// The timestamp format used in the bracketed date field, like `07/Feb/2019:21:11:00 +0000`.
+const DATE_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+/// Reads request records from a stream of Common or Combined Log Format lines,
+/// skipping blank lines.
+#[derive(Debug)]
+pub struct CommonLogReader<R: BufRead> {
+    lines: PositionedLines<R>,
+    parse_line: fn(&str) -> Result<RequestRecord, ParseError>,
+}
+
+impl<R: BufRead> CommonLogReader<R> {
+    /// Wraps a buffered reader of Common Log Format lines.
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: PositionedLines::new(reader),
+            parse_line: parse_common_log_line,
+        }
+    }
+
+    /// Wraps a buffered reader of Combined Log Format lines.
+    pub fn combined(reader: R) -> Self {
+        Self {
+            lines: PositionedLines::new(reader),
+            parse_line: parse_combined_log_line,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CommonLogReader<R> {
+    type Item = Result<RequestRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_line_record(&mut self.lines, self.parse_line)
+    }
+}
+
+/// Parses a single Common Log Format line, such as
+/// `10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234`.
+pub fn parse_common_log_line(line: &str) -> Result<RequestRecord, ParseError> {
+    let mut fields = Fields::new(line);
+    let record = parse_common_fields(&mut fields)?;
+    fields.finish()?;
+    Ok(record)
+}
+
+/// Parses a single Combined Log Format line, which is a Common Log Format line followed by
+/// the quoted Referer and User-Agent, such as
+/// `10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234 "-" "curl/7.64.0"`.
+pub fn parse_combined_log_line(line: &str) -> Result<RequestRecord, ParseError> {
+    let mut fields = Fields::new(line);
+    let mut record = parse_common_fields(&mut fields)?;
+    record.referer = optional(fields.next("referer")?);
+    record.user_agent = optional(fields.next("useragent")?);
+    fields.finish()?;
+    Ok(record)
+}
+
+/// Parses the leading fields shared by the Common and Combined formats.
+fn parse_common_fields(fields: &mut Fields) -> Result<RequestRecord, ParseError> {
+    let remote_host = fields.next("remotehost")?;
+    let _rfc931 = fields.next("rfc931")?;
+    let auth_user = fields.next("authuser")?;
+    let date = fields.next("date")?;
+    let request = fields.next("request")?;
+    let status = fields.next("status")?;
+    let bytes = fields.next("bytes")?;
+
+    Ok(RequestRecord {
+        remote_host: remote_host.parse()?,
+        rfc931: (),
+        auth_user: optional(auth_user),
+        date: parse_date(date)?,
+        request: RequestLine::parse(request),
+        status: parse_field("status", status)?,
+        // Servers log a "-" instead of zero if no body was sent.
+        bytes: if bytes == "-" {
+            0
+        } else {
+            parse_field("bytes", bytes)?
+        },
+        referer: None,
+        user_agent: None,
+        source: None,
+    })
+}
+
+/// Interns an optional field, which servers log as "-" if it's absent.
+fn optional(value: &str) -> Option<DefaultAtom> {
+    match value {
+        "-" | "" => None,
+        value => Some(DefaultAtom::from(value)),
+    }
+}
+
+/// Parses a Common Log Format date, like `07/Feb/2019:21:11:00 +0000`.
+pub(super) fn parse_date(value: &str) -> Result<u32, ParseError> {
+    DateTime::parse_from_str(value, DATE_FORMAT)
+        .ok()
+        .and_then(|date| date.timestamp().try_into().ok())
+        .ok_or_else(|| ParseError::InvalidField {
+            field: "date",
+            value: value.to_string(),
+        })
+}
+
+/// Splits a log line into space-separated fields, where `[bracketed]` and `"quoted"` values
+/// are each treated as a single field with their delimiters removed.
+#[derive(Debug)]
+struct Fields<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: &'a str) -> Self {
+        Self { rest: line.trim() }
+    }
+
+    fn next(&mut self, field: &'static str) -> Result<&'a str, ParseError> {
+        let rest = self.rest.trim_start_matches(' ');
+
+        let (value, rest) = match rest.chars().next() {
+            None => return Err(ParseError::MissingField(field)),
+            Some('[') => {
+                let end = rest.find(']').ok_or_else(|| ParseError::InvalidField {
+                    field,
+                    value: rest.to_string(),
+                })?;
+                (&rest[1..end], &rest[end + 1..])
+            }
+            Some('"') => {
+                let end = closing_quote(rest).ok_or_else(|| ParseError::InvalidField {
+                    field,
+                    value: rest.to_string(),
+                })?;
+                (&rest[1..end], &rest[end + 1..])
+            }
+            Some(_) => {
+                let end = rest.find(' ').unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+
+        self.rest = rest;
+        Ok(value)
+    }
+
+    fn finish(self) -> Result<(), ParseError> {
+        if self.rest.trim().is_empty() {
+            Ok(())
+        } else {
+            Err(ParseError::TrailingContent(self.rest.trim().to_string()))
+        }
+    }
+}
+
+/// Returns the index of the quote closing the quoted string at the start of value,
+/// skipping over any backslash-escaped characters.
+fn closing_quote(value: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in value.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let record = parse_common_log_line(
+            r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#,
+        )
+        .unwrap();
+
+        assert_eq!(record.remote_host, Ipv4Addr::new(10, 0, 0, 2).into());
+        assert_eq!(record.auth_user.as_deref(), Some("apache"));
+        assert_eq!(record.date, 1549573860);
+        assert_eq!(record.request.to_string(), "GET /api/user HTTP/1.0");
+        assert_eq!(record.status, 200);
+        assert_eq!(record.bytes, 1234);
+    }
+
+    #[test]
+    fn test_parse_line_with_offset_and_no_body() {
+        let record = parse_common_log_line(
+            r#"10.0.0.2 - - [07/Feb/2019:16:11:00 -0500] "GET /a\"b HTTP/1.0" 304 -"#,
+        )
+        .unwrap();
+
+        assert_eq!(record.auth_user, None);
+        assert_eq!(record.date, 1549573860);
+        assert_eq!(record.request.to_string(), r#"GET /a\"b HTTP/1.0"#);
+        assert_eq!(record.bytes, 0);
+    }
+
+    #[test]
+    fn test_parse_line_with_ipv6_and_hostname() {
+        let record = parse_common_log_line(
+            r#"2001:db8::2 - - [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#,
+        )
+        .unwrap();
+        assert_eq!(record.remote_host.to_string(), "2001:db8::2");
+
+        let record = parse_common_log_line(
+            r#"client.example.com - - [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#,
+        )
+        .unwrap();
+        assert_eq!(record.remote_host.to_string(), "client.example.com");
+    }
+
+    #[test]
+    fn test_parse_combined_line() {
+        let record = parse_combined_log_line(
+            r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234 "https://example.com/" "curl/7.64.0""#,
+        )
+        .unwrap();
+
+        assert_eq!(record.request.to_string(), "GET /api/user HTTP/1.0");
+        assert_eq!(record.referer.as_deref(), Some("https://example.com/"));
+        assert_eq!(record.user_agent.as_deref(), Some("curl/7.64.0"));
+
+        let record = parse_combined_log_line(
+            r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234 "-" "-""#,
+        )
+        .unwrap();
+
+        assert_eq!(record.referer, None);
+        assert_eq!(record.user_agent, None);
+    }
+
+    #[test]
+    fn test_parse_invalid_lines() {
+        assert_eq!(
+            parse_common_log_line(r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000]"#),
+            Err(ParseError::MissingField("request"))
+        );
+        assert_eq!(
+            parse_common_log_line(r#"10.0.0.2 - apache [yesterday] "GET / HTTP/1.0" 200 1"#),
+            Err(ParseError::InvalidField {
+                field: "date",
+                value: "yesterday".to_string()
+            })
+        );
+        assert_eq!(
+            parse_common_log_line(
+                r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET / HTTP/1.0" 200 1 2"#
+            ),
+            Err(ParseError::TrailingContent("2".to_string()))
+        );
+        assert_eq!(
+            parse_combined_log_line(
+                r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET / HTTP/1.0" 200 1 "-""#
+            ),
+            Err(ParseError::MissingField("useragent"))
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::io::Read;
+
+use csv::StringRecord;
+use serde_derive::{Deserialize, Serialize};
+use string_cache::DefaultAtom;
+
+use super::{parse_field, parse_timestamp, ParseError};
+use crate::{Error, Position, RequestLine, RequestRecord};
+
+/// The headers of columns that are expected in the CSV input data, but may not be used.
+const UNUSED_HEADERS: [&str; 2] = ["rfc931", AUTH_USER_HEADER];
+
+/// The header of the authenticated user column, which is read if it's present and no other
+/// column is configured.
+const AUTH_USER_HEADER: &str = "authuser";
+
+/// A reference to a column of CSV input, by its header or its zero-based index.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum CsvColumn {
+    /// The column at this zero-based index.
+    Index(usize),
+    /// The column with this header.
+    Name(String),
+}
+
+impl From<&str> for CsvColumn {
+    fn from(name: &str) -> Self {
+        CsvColumn::Name(name.to_string())
+    }
+}
+
+/// The columns of CSV input to read each request record field from.
+///
+/// The defaults are the columns in this program's original CSV format, which has the headers
+/// `"remotehost","rfc931","authuser","date","request","status","bytes"`.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvColumns {
+    /// The column for the IP address that the request came from.
+    pub remote_host: CsvColumn,
+    /// The column for the time of the request, as Unix seconds, RFC 3339, or Common Log Format.
+    pub date: CsvColumn,
+    /// The column for the first line of the http request.
+    pub request: CsvColumn,
+    /// The column for the HTTP status code of the response.
+    pub status: CsvColumn,
+    /// The column for the byte length of the response.
+    pub bytes: CsvColumn,
+    /// The column for the referring page, if there is one.
+    pub referer: Option<CsvColumn>,
+    /// The column for the user agent, if there is one.
+    pub user_agent: Option<CsvColumn>,
+    /// The column for the authenticated user. Defaults to the `authuser` column, if there
+    /// is one.
+    pub auth_user: Option<CsvColumn>,
+    /// Whether to allow columns that aren't used for any field.
+    /// Otherwise, they're treated as an error, in case they were meant to be used.
+    pub ignore_unknown: bool,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        Self {
+            remote_host: "remotehost".into(),
+            date: "date".into(),
+            request: "request".into(),
+            status: "status".into(),
+            bytes: "bytes".into(),
+            referer: None,
+            user_agent: None,
+            auth_user: None,
+            ignore_unknown: false,
+        }
+    }
+}
+
+/// The indices of the columns for each field, resolved from CsvColumns for a given header row.
+#[derive(Debug, Clone)]
+struct ColumnIndices {
+    remote_host: usize,
+    date: usize,
+    request: usize,
+    status: usize,
+    bytes: usize,
+    referer: Option<usize>,
+    user_agent: Option<usize>,
+    auth_user: Option<usize>,
+}
+
+impl CsvColumns {
+    /// Finds the index of each field's column in headers, failing with a description of the
+    /// problem if a column is missing or if there are unknown columns that we aren't ignoring.
+    fn resolve(&self, headers: &StringRecord) -> Result<ColumnIndices, String> {
+        let index = |field: &str, column: &CsvColumn| -> Result<usize, String> {
+            match column {
+                CsvColumn::Index(index) if *index < headers.len() => Ok(*index),
+                CsvColumn::Index(index) => Err(format!(
+                    "column index {} for field {} is out of range, there are only {} columns",
+                    index,
+                    field,
+                    headers.len()
+                )),
+                CsvColumn::Name(name) => headers
+                    .iter()
+                    .position(|header| header == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "expected a column {:?} for field {}, but got headers {:?}",
+                            name, field, headers
+                        )
+                    }),
+            }
+        };
+
+        let indices = ColumnIndices {
+            remote_host: index("remotehost", &self.remote_host)?,
+            date: index("date", &self.date)?,
+            request: index("request", &self.request)?,
+            status: index("status", &self.status)?,
+            bytes: index("bytes", &self.bytes)?,
+            referer: self
+                .referer
+                .as_ref()
+                .map(|column| index("referer", column))
+                .transpose()?,
+            user_agent: self
+                .user_agent
+                .as_ref()
+                .map(|column| index("useragent", column))
+                .transpose()?,
+            auth_user: match &self.auth_user {
+                Some(column) => Some(index("authuser", column)?),
+                None => headers.iter().position(|header| header == AUTH_USER_HEADER),
+            },
+        };
+
+        if !self.ignore_unknown {
+            let used = [
+                Some(indices.remote_host),
+                Some(indices.date),
+                Some(indices.request),
+                Some(indices.status),
+                Some(indices.bytes),
+                indices.referer,
+                indices.user_agent,
+                indices.auth_user,
+            ];
+            let unknown = headers
+                .iter()
+                .enumerate()
+                .filter(|(index, header)| {
+                    !used.contains(&Some(*index)) && !UNUSED_HEADERS.contains(header)
+                })
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                return Err(format!(
+                    "unexpected columns {:?} in headers {:?} (these may be ignored with the csv_columns.ignore_unknown option)",
+                    unknown,
+                    headers
+                ));
+            }
+        }
+
+        Ok(indices)
+    }
+}
+
+impl ColumnIndices {
+    fn parse(&self, row: &StringRecord) -> Result<RequestRecord, ParseError> {
+        let field =
+            |name: &'static str, index: usize| row.get(index).ok_or(ParseError::MissingField(name));
+        let optional = |name: &'static str, index: Option<usize>| {
+            index
+                .map(|index| field(name, index))
+                .transpose()
+                .map(|value| match value {
+                    Some("-") | Some("") | None => None,
+                    Some(value) => Some(DefaultAtom::from(value)),
+                })
+        };
+
+        Ok(RequestRecord {
+            remote_host: field("remotehost", self.remote_host)?.parse()?,
+            rfc931: (),
+            auth_user: optional("authuser", self.auth_user)?,
+            date: parse_timestamp(field("date", self.date)?)?,
+            request: RequestLine::parse(field("request", self.request)?),
+            status: parse_field("status", field("status", self.status)?)?,
+            bytes: parse_field("bytes", field("bytes", self.bytes)?)?,
+            referer: optional("referer", self.referer)?,
+            user_agent: optional("useragent", self.user_agent)?,
+            source: None,
+        })
+    }
+}
+
+/// Whether line is a header row that includes the columns required by columns.
+pub fn is_csv_header(line: &str, columns: &CsvColumns) -> bool {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+    match reader.records().next() {
+        Some(Ok(headers)) => columns.resolve(&headers).is_ok(),
+        _ => false,
+    }
+}
+
+/// Reads CSV request records from source, after validating its headers.
+///
+/// Rows repeating the header row are skipped, as they're expected if the source is a followed
+/// file that's been rotated.
+pub fn read_csv<'a>(
+    source: impl Read + 'a,
+    columns: &CsvColumns,
+) -> Result<impl Iterator<Item = Result<RequestRecord, Error>> + 'a, Error> {
+    let mut reader = csv::Reader::from_reader(source);
+
+    // We need to check the headers before reading any rows to cover the edge case that we
+    // have a file with headers, but no rows.
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let indices = columns.resolve(&headers).map_err(Error::Header)?;
+
+    log::debug!("validated headers, using columns {:?}", indices);
+
+    let rows = reader.into_records().filter(move |row| match row {
+        Ok(row) => *row != headers,
+        Err(_) => true,
+    });
+
+    Ok(rows.map(move |row| {
+        let row = row.map_err(csv_error)?;
+        indices.parse(&row).map_err(|source| Error::Parse {
+            position: csv_position(row.position()),
+            source,
+        })
+    }))
+}
+
+/// Converts a position from the csv reader, which may be unknown.
+fn csv_position(position: Option<&csv::Position>) -> Position {
+    position.map_or(Position { line: 0, offset: 0 }, |position| Position {
+        line: position.line(),
+        offset: position.byte(),
+    })
+}
+
+/// Converts an error from the csv reader into an I/O or parse error.
+fn csv_error(error: csv::Error) -> Error {
+    let position = csv_position(error.position());
+    let message = error.to_string();
+    let source = match error.into_kind() {
+        csv::ErrorKind::Io(error) => return Error::Io(error),
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => ParseError::InvalidCsv(format!(
+            "expected {} fields like the header row, but found {}",
+            expected_len, len
+        )),
+        _ => ParseError::InvalidCsv(message),
+    };
+    Error::Parse { position, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(headers: &[&str]) -> StringRecord {
+        headers.iter().collect()
+    }
+
+    #[test]
+    fn test_resolve_default_columns() {
+        let columns = CsvColumns::default();
+
+        let reordered = headers(&[
+            "date",
+            "remotehost",
+            "rfc931",
+            "authuser",
+            "bytes",
+            "status",
+            "request",
+        ]);
+        let indices = columns.resolve(&reordered).unwrap();
+        assert_eq!(indices.date, 0);
+        assert_eq!(indices.request, 6);
+        assert_eq!(indices.auth_user, Some(3));
+
+        let missing = headers(&["remotehost", "date", "request", "status"]);
+        assert!(columns.resolve(&missing).is_err());
+
+        let extra = headers(&["remotehost", "date", "request", "status", "bytes", "dc"]);
+        assert!(columns.resolve(&extra).is_err());
+        assert!(CsvColumns {
+            ignore_unknown: true,
+            ..CsvColumns::default()
+        }
+        .resolve(&extra)
+        .is_ok());
+    }
+
+    #[test]
+    fn test_parse_mapped_columns() {
+        let columns = CsvColumns {
+            remote_host: "client".into(),
+            date: CsvColumn::Index(1),
+            request: "request_line".into(),
+            status: "http_status".into(),
+            bytes: "response_size".into(),
+            user_agent: Some("agent".into()),
+            referer: None,
+            auth_user: Some("login".into()),
+            ignore_unknown: true,
+        };
+        let indices = columns
+            .resolve(&headers(&[
+                "client",
+                "ts",
+                "request_line",
+                "http_status",
+                "response_size",
+                "agent",
+                "region",
+                "login",
+            ]))
+            .unwrap();
+
+        let record = indices
+            .parse(&headers(&[
+                "10.0.0.2",
+                "2019-02-07T21:11:00Z",
+                "GET /api/user HTTP/1.0",
+                "200",
+                "1234",
+                "curl/7.64.0",
+                "us-east",
+                "alice",
+            ]))
+            .unwrap();
+
+        assert_eq!(record.date, 1549573860);
+        assert_eq!(record.request.to_string(), "GET /api/user HTTP/1.0");
+        assert_eq!(record.user_agent.as_deref(), Some("curl/7.64.0"));
+        assert_eq!(record.auth_user.as_deref(), Some("alice"));
+    }
+}
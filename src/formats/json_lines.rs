@@ -0,0 +1,297 @@
+use std::io::BufRead;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use string_cache::DefaultAtom;
+
+use super::{next_line_record, parse_field, parse_timestamp, ParseError, PositionedLines};
+use crate::{Error, RequestLine, RequestRecord};
+
+/// The keys to look up in each JSON object for each request record field.
+///
+/// Each field has a list of candidate keys, and the first one present (and not null) in an
+/// object is used. Keys may contain dots to look up values in nested objects. The defaults
+/// include the names used by this program's CSV headers, nginx's variables, and Envoy's
+/// access log operators.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonFields {
+    /// Keys for the IP address that the request came from. A port suffix is ignored.
+    pub remote_host: Vec<String>,
+    /// Keys for the time of the request, as Unix seconds, RFC 3339, or Common Log Format.
+    pub date: Vec<String>,
+    /// Keys for the first line of the http request. If none are present, the request line
+    /// is assembled from the method, path and protocol instead.
+    pub request: Vec<String>,
+    /// Keys for the HTTP method, used if there's no request line.
+    pub method: Vec<String>,
+    /// Keys for the request path, used if there's no request line.
+    pub path: Vec<String>,
+    /// Keys for the HTTP protocol version, used if there's no request line.
+    pub protocol: Vec<String>,
+    /// Keys for the HTTP status code of the response.
+    pub status: Vec<String>,
+    /// Keys for the byte length of the response.
+    pub bytes: Vec<String>,
+    /// Keys for the referring page, which is optional.
+    pub referer: Vec<String>,
+    /// Keys for the user agent, which is optional.
+    pub user_agent: Vec<String>,
+    /// Keys for the authenticated user, which is optional.
+    pub auth_user: Vec<String>,
+}
+
+impl Default for JsonFields {
+    fn default() -> Self {
+        fn keys(keys: &[&str]) -> Vec<String> {
+            keys.iter().map(|key| key.to_string()).collect()
+        }
+
+        Self {
+            remote_host: keys(&[
+                "remotehost",
+                "remote_addr",
+                "client_ip",
+                "downstream_remote_address",
+            ]),
+            date: keys(&[
+                "date",
+                "timestamp",
+                "time",
+                "time_iso8601",
+                "time_local",
+                "msec",
+                "start_time",
+                "@timestamp",
+            ]),
+            request: keys(&["request"]),
+            method: keys(&["method", "request_method"]),
+            path: keys(&["path", "request_uri", "uri"]),
+            protocol: keys(&["protocol", "server_protocol"]),
+            status: keys(&["status", "response_code"]),
+            bytes: keys(&["bytes", "body_bytes_sent", "bytes_sent"]),
+            referer: keys(&["referer", "http_referer"]),
+            user_agent: keys(&["useragent", "user_agent", "http_user_agent"]),
+            auth_user: keys(&["authuser", "remote_user", "user"]),
+        }
+    }
+}
+
+/// Reads request records from a stream of JSON objects, one per line, skipping blank lines.
+#[derive(Debug)]
+pub struct JsonLinesReader<R: BufRead> {
+    lines: PositionedLines<R>,
+    fields: JsonFields,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    /// Wraps a buffered reader of JSON Lines, which will be mapped to records using fields.
+    pub fn new(reader: R, fields: JsonFields) -> Self {
+        Self {
+            lines: PositionedLines::new(reader),
+            fields,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesReader<R> {
+    type Item = Result<RequestRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fields = &self.fields;
+        next_line_record(&mut self.lines, |line| parse_json_line(line, fields))
+    }
+}
+
+/// Whether line is a JSON object, and could therefore be JSON Lines input.
+pub(super) fn is_json_object(line: &str) -> bool {
+    serde_json::from_str::<Value>(line)
+        .map(|value| value.is_object())
+        .unwrap_or(false)
+}
+
+/// Parses a single line containing a JSON object, such as
+/// `{"remote_addr": "10.0.0.2", "msec": 1549573860, "request": "GET /api/user HTTP/1.0", "status": 200, "bytes": 1234}`,
+/// using fields to find the value for each field of the record.
+pub fn parse_json_line(line: &str, fields: &JsonFields) -> Result<RequestRecord, ParseError> {
+    let object = serde_json::from_str::<Value>(line)
+        .map_err(|error| ParseError::InvalidJson(error.to_string()))?;
+    if !object.is_object() {
+        return Err(ParseError::InvalidJson("expected an object".to_string()));
+    }
+
+    let request = match lookup(&object, &fields.request) {
+        Some(request) => text("request", request)?,
+        None => {
+            let method = text("method", required(&object, &fields.method, "method")?)?;
+            let path = text("path", required(&object, &fields.path, "path")?)?;
+            match lookup(&object, &fields.protocol) {
+                Some(protocol) => format!("{} {} {}", method, path, text("protocol", protocol)?),
+                None => format!("{} {}", method, path),
+            }
+        }
+    };
+
+    Ok(RequestRecord {
+        remote_host: text(
+            "remotehost",
+            required(&object, &fields.remote_host, "remotehost")?,
+        )?
+        .parse()?,
+        rfc931: (),
+        auth_user: optional(lookup(&object, &fields.auth_user)),
+        date: parse_json_date(required(&object, &fields.date, "date")?)?,
+        request: RequestLine::parse(&request),
+        status: parse_field(
+            "status",
+            &text("status", required(&object, &fields.status, "status")?)?,
+        )?,
+        bytes: match text("bytes", required(&object, &fields.bytes, "bytes")?)?.as_str() {
+            "-" => 0,
+            bytes => parse_field("bytes", bytes)?,
+        },
+        referer: optional(lookup(&object, &fields.referer)),
+        user_agent: optional(lookup(&object, &fields.user_agent)),
+        source: None,
+    })
+}
+
+/// Finds the value of the first key present and not null in object.
+fn lookup<'a>(object: &'a Value, keys: &[String]) -> Option<&'a Value> {
+    keys.iter().find_map(|key| {
+        key.split('.')
+            .try_fold(object, |value, part| value.get(part))
+            .filter(|value| !value.is_null())
+    })
+}
+
+fn required<'a>(
+    object: &'a Value,
+    keys: &[String],
+    field: &'static str,
+) -> Result<&'a Value, ParseError> {
+    lookup(object, keys).ok_or(ParseError::MissingField(field))
+}
+
+/// The value of a string or number, as a string.
+fn text(field: &'static str, value: &Value) -> Result<String, ParseError> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        value => Err(ParseError::InvalidField {
+            field,
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Interns an optional string value, which may be logged as "-" if it's absent.
+fn optional(value: Option<&Value>) -> Option<DefaultAtom> {
+    match value {
+        Some(Value::String(value)) if value != "-" && !value.is_empty() => {
+            Some(DefaultAtom::from(value.as_str()))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a date from a JSON number or string.
+fn parse_json_date(value: &Value) -> Result<u32, ParseError> {
+    match value {
+        Value::Number(seconds) => parse_timestamp(&seconds.to_string()),
+        Value::String(date) => parse_timestamp(date),
+        value => Err(ParseError::InvalidField {
+            field: "date",
+            value: value.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_nginx_line() {
+        let record = parse_json_line(
+            r#"{"remote_addr": "10.0.0.2", "msec": "1549573860.123", "request": "GET /api/user HTTP/1.0", "status": "200", "body_bytes_sent": 1234, "http_referer": "-", "http_user_agent": "curl/7.64.0"}"#,
+            &JsonFields::default(),
+        )
+        .unwrap();
+
+        assert_eq!(record.remote_host, Ipv4Addr::new(10, 0, 0, 2).into());
+        assert_eq!(record.date, 1549573860);
+        assert_eq!(record.request.to_string(), "GET /api/user HTTP/1.0");
+        assert_eq!(record.status, 200);
+        assert_eq!(record.bytes, 1234);
+        assert_eq!(record.referer, None);
+        assert_eq!(record.user_agent.as_deref(), Some("curl/7.64.0"));
+    }
+
+    #[test]
+    fn test_parse_envoy_line() {
+        let record = parse_json_line(
+            r#"{"downstream_remote_address": "10.0.0.2:51234", "start_time": "2019-02-07T21:11:00.152Z", "method": "POST", "path": "/report", "protocol": "HTTP/1.1", "response_code": 503, "bytes_sent": 0}"#,
+            &JsonFields::default(),
+        )
+        .unwrap();
+
+        assert_eq!(record.remote_host, Ipv4Addr::new(10, 0, 0, 2).into());
+        assert_eq!(record.date, 1549573860);
+        assert_eq!(record.request.to_string(), "POST /report HTTP/1.1");
+        assert_eq!(record.status, 503);
+        assert_eq!(record.bytes, 0);
+    }
+
+    #[test]
+    fn test_parse_custom_fields() {
+        let fields = JsonFields {
+            remote_host: vec!["client.ip".to_string()],
+            date: vec!["ts".to_string()],
+            ..JsonFields::default()
+        };
+
+        let record = parse_json_line(
+            r#"{"client": {"ip": "10.0.0.4"}, "ts": 1549573860, "request": "GET / HTTP/1.0", "status": 200, "bytes": "-"}"#,
+            &fields,
+        )
+        .unwrap();
+
+        assert_eq!(record.remote_host, Ipv4Addr::new(10, 0, 0, 4).into());
+        assert_eq!(record.date, 1549573860);
+
+        assert_eq!(
+            parse_json_line(
+                r#"{"remote_addr": "10.0.0.4", "ts": 1549573860, "request": "GET / HTTP/1.0", "status": 200, "bytes": 1}"#,
+                &fields,
+            ),
+            Err(ParseError::MissingField("remotehost"))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_lines() {
+        let fields = JsonFields::default();
+
+        assert!(matches!(
+            parse_json_line(r#"{"status": 200"#, &fields),
+            Err(ParseError::InvalidJson(_))
+        ));
+        assert!(matches!(
+            parse_json_line("[200]", &fields),
+            Err(ParseError::InvalidJson(_))
+        ));
+        assert_eq!(
+            parse_json_line(
+                r#"{"remotehost": "10.0.0.4", "date": true, "request": "GET / HTTP/1.0", "status": 200, "bytes": 1}"#,
+                &fields,
+            ),
+            Err(ParseError::InvalidField {
+                field: "date",
+                value: "true".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Readers for the supported formats of request log input.
+
+mod common_log;
+mod csv_format;
+mod json_lines;
+
+use std::{
+    convert::TryInto,
+    fmt,
+    io::{self, BufRead},
+    str::FromStr,
+};
+
+use chrono::DateTime;
+
+use serde_derive::{Deserialize, Serialize};
+
+use self::common_log::parse_date;
+pub use self::common_log::{parse_combined_log_line, parse_common_log_line, CommonLogReader};
+use self::csv_format::{is_csv_header, read_csv};
+pub use self::csv_format::{CsvColumn, CsvColumns};
+use self::json_lines::is_json_object;
+pub use self::json_lines::{parse_json_line, JsonFields, JsonLinesReader};
+use crate::{Config, Error, Position, RequestRecord};
+
+/// The number of non-blank lines to look at when detecting the format of input.
+pub(crate) const DETECTION_LINES: usize = 5;
+
+/// An error encountered while parsing a single line of request log input.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line ended before all of the expected fields were read.
+    #[error("missing field {0}")]
+    MissingField(&'static str),
+    /// A field was present, but its value couldn't be interpreted.
+    #[error("invalid value for field {field}: {value:?}")]
+    InvalidField {
+        /// The name of the field.
+        field: &'static str,
+        /// The raw value of the field.
+        value: String,
+    },
+    /// The line contained extra content after the last expected field.
+    #[error("unexpected trailing content: {0:?}")]
+    TrailingContent(String),
+    /// The line wasn't a valid JSON object.
+    #[error("invalid JSON object: {0}")]
+    InvalidJson(String),
+    /// The row wasn't valid CSV.
+    #[error("invalid CSV row: {0}")]
+    InvalidCsv(String),
+    /// The line wasn't valid UTF-8.
+    #[error("invalid UTF-8")]
+    InvalidUtf8,
+}
+
+/// An iterator of request records read from some input.
+pub type Records<'a> = Box<dyn Iterator<Item = Result<RequestRecord, Error>> + 'a>;
+
+/// A supported format of request log input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    /// CSV with a header row, whose columns are mapped to fields by the config.
+    Csv,
+    /// Common Log Format, as written by Apache and nginx by default.
+    Common,
+    /// Combined Log Format, which adds the referer and user agent to the Common Log Format.
+    Combined,
+    /// JSON Lines, with one object per line whose keys are mapped to fields by the config.
+    #[serde(rename = "json")]
+    JsonLines,
+}
+
+impl InputFormat {
+    /// All of the supported formats, in the order that detection considers them.
+    pub const ALL: [InputFormat; 4] = [
+        InputFormat::Csv,
+        InputFormat::JsonLines,
+        InputFormat::Combined,
+        InputFormat::Common,
+    ];
+
+    /// The name of this format, as used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Csv => "csv",
+            InputFormat::Common => "common",
+            InputFormat::Combined => "combined",
+            InputFormat::JsonLines => "json",
+        }
+    }
+
+    /// Guesses the format of input from a sample of its first lines.
+    ///
+    /// Picks the format that parses the most non-blank lines in the sample, so that a few bad
+    /// lines are left to be rejected when reading, or None if no format parses any of them. A
+    /// partial final line should be excluded from the sample, or it may fail to parse.
+    pub fn detect(sample: &str, config: &Config) -> Option<InputFormat> {
+        let mut lines = sample.lines().filter(|line| !line.trim().is_empty());
+
+        let first = lines.next()?;
+        if is_csv_header(first, &config.csv_columns) {
+            return Some(InputFormat::Csv);
+        }
+
+        Self::ALL
+            .iter()
+            .map(|&format| {
+                let parsed = std::iter::once(first)
+                    .chain(lines.clone())
+                    .filter(|line| format.parses_line(line))
+                    .count();
+                (format, parsed)
+            })
+            .filter(|&(_, parsed)| parsed > 0)
+            // The first of the formats that parse the most lines.
+            .min_by_key(|&(_, parsed)| std::cmp::Reverse(parsed))
+            .map(|(format, _)| format)
+    }
+
+    /// Whether line is a valid record in this format, if it's a line-based format.
+    fn parses_line(self, line: &str) -> bool {
+        match self {
+            InputFormat::Csv => false,
+            InputFormat::Common => parse_common_log_line(line).is_ok(),
+            InputFormat::Combined => parse_combined_log_line(line).is_ok(),
+            // The keys are checked when reading, so that a misconfigured mapping produces
+            // a specific error instead of a failure to detect the format.
+            InputFormat::JsonLines => is_json_object(line),
+        }
+    }
+
+    /// Reads request records in this format from source.
+    ///
+    /// Each record's authenticated user is discarded unless the config keeps it.
+    pub fn read<'a>(
+        self,
+        source: impl BufRead + 'a,
+        config: &Config,
+    ) -> Result<Records<'a>, Error> {
+        let records: Records<'a> = match self {
+            InputFormat::Csv => Box::new(read_csv(source, &config.csv_columns)?),
+            InputFormat::Common => Box::new(CommonLogReader::new(source)),
+            InputFormat::Combined => Box::new(CommonLogReader::combined(source)),
+            InputFormat::JsonLines => {
+                Box::new(JsonLinesReader::new(source, config.json_fields.clone()))
+            }
+        };
+
+        if config.keep_auth_user {
+            return Ok(records);
+        }
+        Ok(Box::new(records.map(|record| {
+            record.map(|record| RequestRecord {
+                auth_user: None,
+                ..record
+            })
+        })))
+    }
+}
+
+/// The lines of a buffered reader, without their line endings, with the position that each
+/// starts at.
+#[derive(Debug)]
+struct PositionedLines<R> {
+    reader: R,
+    /// The position of the next line.
+    next: Position,
+}
+
+impl<R: BufRead> PositionedLines<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            next: Position { line: 1, offset: 0 },
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PositionedLines<R> {
+    type Item = io::Result<(Position, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let length = match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(length) => length,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let position = self.next;
+        self.next = Position {
+            line: position.line + 1,
+            offset: position.offset + length as u64,
+        };
+
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Some(Ok((position, line)))
+    }
+}
+
+/// Reads the next non-blank line from lines and parses it into a record, adding the line's
+/// position to any error.
+fn next_line_record(
+    lines: &mut PositionedLines<impl BufRead>,
+    parse_line: impl FnOnce(&str) -> Result<RequestRecord, ParseError>,
+) -> Option<Result<RequestRecord, Error>> {
+    loop {
+        let (position, line) = match lines.next()? {
+            Ok(line) => line,
+            Err(error) => return Some(Err(error.into())),
+        };
+
+        // Checked per line, so that a stray invalid byte doesn't end the input.
+        let result = match std::str::from_utf8(&line) {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => parse_line(line),
+            Err(_) => Err(ParseError::InvalidUtf8),
+        };
+
+        return Some(result.map_err(|source| Error::Parse { position, source }));
+    }
+}
+
+/// Parses a field's value using its FromStr implementation.
+fn parse_field<T: FromStr>(field: &'static str, value: &str) -> Result<T, ParseError> {
+    value.parse().map_err(|_| ParseError::InvalidField {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Parses a timestamp from Unix seconds, which may be fractional, or from an RFC 3339 or
+/// Common Log Format date.
+fn parse_timestamp(value: &str) -> Result<u32, ParseError> {
+    let invalid = || ParseError::InvalidField {
+        field: "date",
+        value: value.to_string(),
+    };
+
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(seconds)
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .and_then(|seconds| (seconds.floor() as u64).try_into().ok())
+            .ok_or_else(invalid);
+    }
+
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(date) => date.timestamp().try_into().map_err(|_| invalid()),
+        Err(_) => parse_date(value),
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown input format {:?}, expected one of: {}",
+                    name,
+                    Self::ALL
+                        .iter()
+                        .map(|format| format.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let config = Config::default();
+
+        assert_eq!(
+            InputFormat::detect(include_str!("../../samples/input.csv"), &config),
+            Some(InputFormat::Csv)
+        );
+        assert_eq!(
+            InputFormat::detect(
+                r#"
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.4 - apache [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0" 500 1307
+"#,
+                &config
+            ),
+            Some(InputFormat::Common)
+        );
+        assert_eq!(
+            InputFormat::detect(
+                r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234 "-" "curl/7.64.0""#,
+                &config
+            ),
+            Some(InputFormat::Combined)
+        );
+        assert_eq!(
+            InputFormat::detect(r#"{"remote_addr": "10.0.0.2", "status": 200}"#, &config),
+            Some(InputFormat::JsonLines)
+        );
+        assert_eq!(
+            InputFormat::detect(
+                r#"not a log line
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+"#,
+                &config
+            ),
+            Some(InputFormat::Common)
+        );
+        assert_eq!(InputFormat::detect("", &config), None);
+        assert_eq!(InputFormat::detect("1 2\n3 4\n5", &config), None);
+    }
+
+    #[test]
+    fn test_names() {
+        for format in InputFormat::ALL.iter() {
+            assert_eq!(format.name().parse(), Ok(*format));
+        }
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+}
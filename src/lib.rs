@@ -2,33 +2,79 @@
 #![warn(missing_docs, missing_debug_implementations)]
 
 use std::{
-    io::{Read, Write},
+    cell::RefCell,
+    fs,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    path::Path,
     rc::Rc,
-    str,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::ensure;
+use string_cache::DefaultAtom;
 
+mod decompress;
+mod error;
+mod exporters;
+mod follow;
+mod formats;
+mod merged_request_iterator;
+mod metrics;
 mod models;
 mod monitors;
+mod notifiers;
+mod rejected_records;
+mod render;
+mod sections;
 mod sorted_request_iterator;
+mod syslog;
+
+pub use self::decompress::{decompress, Compression};
+pub use self::error::{Error, Position};
+pub use self::exporters::{
+    Exporter, Exporters, GraphiteConfig, GraphiteExporter, InfluxDbConfig, InfluxDbExporter,
+    StatsdConfig, StatsdExporter, Transport,
+};
+pub use self::follow::FollowReader;
+use self::formats::DETECTION_LINES;
+pub use self::formats::{
+    parse_combined_log_line, parse_common_log_line, parse_json_line, CommonLogReader, CsvColumn,
+    CsvColumns, InputFormat, JsonFields, JsonLinesReader, ParseError, Records,
+};
+pub use self::merged_request_iterator::MergedRequestIterator;
+pub use self::metrics::{Metrics, MetricsServer};
+pub use self::models::{
+    Alert, Config, ConfigError, InvalidConfigField, MonitorEvent, RemoteHost, RequestLine,
+    RequestRecord, StatsWindow, UserAlert,
+};
+pub use self::monitors::{ChunkedStatsMonitor, Monitor, RollingAlertsMonitor, UserAlertsMonitor};
+use self::notifiers::check_template;
+pub use self::notifiers::{
+    AlertmanagerConfig, AlertmanagerNotifier, Delivery, Notifier, Notifiers, WebhookConfig,
+    WebhookNotifier,
+};
+pub use self::rejected_records::ErrorPolicy;
+use self::rejected_records::{skip_rejected, RejectedRecords};
+pub use self::render::{render_json, render_text, OutputFormat};
+use self::sections::check_pattern;
+pub use self::sections::{PathRule, SectionConfig, Sections, SegmentDetector};
+pub use self::sorted_request_iterator::{
+    LatePolicy, LateRecordError, SortedRequestIterator, SortingStats, TimestampErrorTuning,
+};
+use self::sorted_request_iterator::{SortedRecords, SortingBuffer};
+pub use self::syslog::{parse_syslog_message, SyslogListener};
+
+/// The largest allowed maximum_timestamp_error, in seconds. Records are held in memory for up
+/// to twice as long while they're sorted.
+const MAX_TIMESTAMP_ERROR: u32 = 24 * 60 * 60;
+
+/// How often monitors are ticked while streaming live input.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
 
-pub use self::models::{Config, RequestRecord};
-pub use self::monitors::{ChunkedStatsMonitor, Monitor, RollingAlertsMonitor};
-pub use self::sorted_request_iterator::SortedRequestIterator;
-
-/// The headers expected in the CSV input data.
-const CSV_HEADERS: [&str; 7] = [
-    "remotehost",
-    "rfc931",
-    "authuser",
-    "date",
-    "request",
-    "status",
-    "bytes",
-];
-
-// TODO: load config from json
 impl Default for Config {
     fn default() -> Self {
         // The default config specified in the assignment description.
@@ -36,62 +82,724 @@ impl Default for Config {
             stats_window: 10,
             alert_window: 120,
             alert_rate: 10,
+            keep_auth_user: false,
+            user_alert_rate: None,
+            sections: SectionConfig::default(),
             maximum_timestamp_error: 1,
+            timestamp_error_tuning: None,
+            late_policy: LatePolicy::default(),
+            on_error: None,
+            input_format: None,
+            csv_columns: CsvColumns::default(),
+            json_fields: JsonFields::default(),
+            output_format: OutputFormat::default(),
+            metrics_address: None,
+            exporters: Exporters::default(),
+            notifiers: Notifiers::default(),
         }
     }
 }
 
-/// Reads CSV request records from source, runs monitors according to config, writing their output to sink.
+impl Config {
+    /// Loads a config from a TOML, JSON or YAML file, depending on its extension.
+    ///
+    /// Fields that are missing from the file have their default values. Errors name the key
+    /// that couldn't be loaded, such as `exporters.statsd.address`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let invalid = |reason: String| Error::ConfigFile {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|error| invalid(format!("failed to read it: {}", error)))?;
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        let result = match extension.as_deref() {
+            Some("toml") => serde_path_to_error::deserialize(toml::Deserializer::new(&contents))
+                .map_err(|error| key_error(error.path(), error.inner())),
+            Some("json") => {
+                serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&contents))
+                    .map_err(|error| key_error(error.path(), error.inner()))
+            }
+            Some("yaml") | Some("yml") => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(&contents))
+                    .map_err(|error| key_error(error.path(), error.inner()))
+            }
+            _ => {
+                Err("unknown format, expected a .toml, .json, .yaml or .yml extension".to_string())
+            }
+        };
+
+        result.map_err(invalid)
+    }
+}
+
+impl Config {
+    /// Checks that every setting has a usable value, returning an error describing each one
+    /// that doesn't.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut fields = Vec::new();
+        let mut check = |valid: bool, key: &str, reason: &str| {
+            if !valid {
+                fields.push(InvalidConfigField {
+                    key: key.to_string(),
+                    reason: reason.to_string(),
+                });
+            }
+        };
+
+        check(
+            self.stats_window > 0,
+            "stats_window",
+            "must be at least 1 second",
+        );
+        check(
+            self.alert_window > 0,
+            "alert_window",
+            "must be at least 1 second",
+        );
+        check(
+            self.alert_rate > 0,
+            "alert_rate",
+            "must be at least 1 request per second, or the alert would always be triggered",
+        );
+        if let Some(user_alert_rate) = self.user_alert_rate {
+            check(
+                user_alert_rate > 0,
+                "user_alert_rate",
+                "must be at least 1 request per second, or the alert would always be triggered",
+            );
+            check(
+                self.keep_auth_user,
+                "user_alert_rate",
+                "requires keep_auth_user, or there are no users to alert on",
+            );
+        }
+        check(
+            self.sections.depth > 0,
+            "sections.depth",
+            "must be at least 1 path segment",
+        );
+        for (index, rule) in self.sections.rules.iter().enumerate() {
+            if let Err(reason) = check_pattern(&rule.pattern) {
+                check(
+                    false,
+                    &format!("sections.rules[{}].pattern", index),
+                    &reason,
+                );
+            }
+        }
+        check(
+            self.maximum_timestamp_error <= MAX_TIMESTAMP_ERROR,
+            "maximum_timestamp_error",
+            &format!(
+                "must be at most {} seconds (one day), as records are held in memory for twice as long",
+                MAX_TIMESTAMP_ERROR
+            ),
+        );
+
+        if let Some(tuning) = &self.timestamp_error_tuning {
+            check(
+                tuning.warm_up_seconds > 0 && tuning.warm_up_seconds <= MAX_TIMESTAMP_ERROR,
+                "timestamp_error_tuning.warm_up_seconds",
+                &format!(
+                    "must be from 1 to {} seconds (one day)",
+                    MAX_TIMESTAMP_ERROR
+                ),
+            );
+            check(
+                tuning.percentile > 0 && tuning.percentile <= 100,
+                "timestamp_error_tuning.percentile",
+                "must be from 1 to 100",
+            );
+        }
+
+        let required_json_fields = [
+            ("remote_host", &self.json_fields.remote_host),
+            ("date", &self.json_fields.date),
+            ("status", &self.json_fields.status),
+            ("bytes", &self.json_fields.bytes),
+        ];
+        for (field, keys) in required_json_fields.iter() {
+            check(
+                !keys.is_empty(),
+                &format!("json_fields.{}", field),
+                "must list at least one key",
+            );
+        }
+        check(
+            !self.json_fields.request.is_empty() || !self.json_fields.path.is_empty(),
+            "json_fields.request",
+            "must list at least one key, unless json_fields.path does",
+        );
+
+        let exporters = &self.exporters;
+        let addresses = [
+            ("statsd", exporters.statsd.as_ref().map(|c| &c.address)),
+            ("graphite", exporters.graphite.as_ref().map(|c| &c.address)),
+            ("influxdb", exporters.influxdb.as_ref().map(|c| &c.address)),
+        ];
+        for (exporter, address) in addresses.iter() {
+            if let Some(address) = address {
+                check(
+                    !address.trim().is_empty(),
+                    &format!("exporters.{}.address", exporter),
+                    "must be a host and port, such as 127.0.0.1:8125",
+                );
+            }
+        }
+
+        let notifiers = &self.notifiers;
+        let endpoints = notifiers
+            .webhooks
+            .iter()
+            .enumerate()
+            .map(|(index, c)| (format!("webhooks[{}]", index), &c.url, &c.delivery))
+            .chain(
+                notifiers
+                    .alertmanagers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, c)| (format!("alertmanagers[{}]", index), &c.url, &c.delivery)),
+            );
+        for (index, webhook) in notifiers.webhooks.iter().enumerate() {
+            if let Some(template) = &webhook.body_template {
+                if let Err(reason) = check_template(template) {
+                    check(
+                        false,
+                        &format!("notifiers.webhooks[{}].body_template", index),
+                        &reason,
+                    );
+                }
+            }
+        }
+        for (index, alertmanager) in notifiers.alertmanagers.iter().enumerate() {
+            check(
+                alertmanager.repost_seconds > 0,
+                &format!("notifiers.alertmanagers[{}].repost_seconds", index),
+                "must be at least 1 second",
+            );
+        }
+        for (notifier, url, delivery) in endpoints {
+            check(
+                url.starts_with("http://") || url.starts_with("https://"),
+                &format!("notifiers.{}.url", notifier),
+                "must be an http:// or https:// URL",
+            );
+            check(
+                delivery.timeout_seconds > 0,
+                &format!("notifiers.{}.delivery.timeout_seconds", notifier),
+                "must be at least 1 second",
+            );
+            check(
+                delivery.queue_capacity > 0,
+                &format!("notifiers.{}.delivery.queue_capacity", notifier),
+                "must be at least 1, or every notification would be dropped",
+            );
+        }
+
+        if fields.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { fields })
+        }
+    }
+}
+
+/// Describes an error loading the config key at path.
+fn key_error(path: &serde_path_to_error::Path, error: impl std::fmt::Display) -> String {
+    let key = path.to_string();
+    let message = error.to_string();
+    if key == "." {
+        return message;
+    }
+    // Some formats' errors already start with the key.
+    let message = message
+        .strip_prefix(&format!("{}: ", key))
+        .unwrap_or(&message);
+    format!("invalid value for key `{}`: {}", key, message)
+}
+
+/// Reads request records from source, decompressing it if it's gzip, zstd or bzip2.
+///
+/// If the config doesn't specify an input format, it's detected from the first lines of the
+/// decompressed source.
+pub fn read_records<'a>(source: impl Read + 'a, config: &Config) -> Result<Records<'a>, Error> {
+    open_records(source, config, false)
+}
+
+/// Reads request records from source like [read_records]. If live, format detection stops at
+/// the first line that can be parsed, rather than waiting for more lines to arrive.
+fn open_records<'a>(
+    source: impl Read + 'a,
+    config: &Config,
+    live: bool,
+) -> Result<Records<'a>, Error> {
+    let mut source = BufReader::new(decompress(source)?);
+
+    // Lines consumed from source for format detection, which we need to put back in front.
+    let mut sample = String::new();
+
+    let format = match config.input_format {
+        Some(format) => format,
+        None => {
+            let mut sample_lines = 0;
+            while sample_lines < DETECTION_LINES {
+                let previous_length = sample.len();
+                if source.read_line(&mut sample)? == 0 {
+                    break;
+                }
+                if !sample[previous_length..].trim().is_empty() {
+                    sample_lines += 1;
+                    if live && InputFormat::detect(&sample, config).is_some() {
+                        break;
+                    }
+                }
+            }
+
+            let format = InputFormat::detect(&sample, config).ok_or(Error::UndetectedFormat {
+                lines: DETECTION_LINES,
+            })?;
+            log::info!("detected input format: {}", format);
+            format
+        }
+    };
+
+    format.read(BufReader::new(Cursor::new(sample).chain(source)), config)
+}
+
+/// Reads request records from source, runs monitors according to config, writing their output
+/// to sink.
+///
+/// Source is read as described for [read_records], after the config has been validated.
+/// Records that can't be parsed are handled according to the config's on_error policy,
+/// failing by default.
 pub fn monitor_stream(
     source: &mut impl Read,
     sink: &mut impl Write,
     config: &Config,
-) -> anyhow::Result<()> {
-    let mut reader = csv::Reader::from_reader(source);
-
-    // We need to manually check the headers to cover the edge case that we have a file
-    // with headers, but no rows. (Serde will implicitly check the headers when deserializing
-    // a row into a struct, but if there are no rows the invalid headers would be ignored.)
-    ensure!(
-        reader.headers()? == *&CSV_HEADERS[..],
-        "expected headers {:?}, but got {:?}",
-        CSV_HEADERS,
-        reader.headers()?
-    );
+) -> Result<(), Error> {
+    config.validate()?;
+
+    let rejected = RefCell::new(RejectedRecords::new(
+        config.on_error.clone().unwrap_or(ErrorPolicy::Fail),
+    ));
+    let error = RefCell::new(None);
+
+    let records = skip_rejected(read_records(source, config)?, None, &rejected, &error);
+    let result = monitor_records(records, sink, config);
+    rejected.borrow().report();
+    result?;
+
+    match error.into_inner() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Reads request records from several named sources, merging them into a single chronological
+/// stream, runs monitors according to config, writing their output to sink.
+///
+/// Each source is read as described for [read_records], and each record is tagged with the
+/// name of its source. Records that can't be parsed are handled according to the config's
+/// on_error policy, failing by default. If a source fails partway through, the others are
+/// still read to the end before the error is returned.
+pub fn monitor_streams<'a>(
+    sources: Vec<(String, Box<dyn Read + 'a>)>,
+    sink: &mut impl Write,
+    config: &Config,
+) -> Result<(), Error> {
+    config.validate()?;
+
+    let rejected = RefCell::new(RejectedRecords::new(
+        config.on_error.clone().unwrap_or(ErrorPolicy::Fail),
+    ));
+    let error = RefCell::new(None);
+
+    let mut streams = Vec::new();
+    for (name, source) in sources {
+        let records = read_records(source, config).map_err(|error| Error::Input {
+            name: name.clone(),
+            source: Box::new(error),
+        })?;
+        let name = DefaultAtom::from(name);
+
+        let records = skip_rejected(records, Some(name.clone()), &rejected, &error);
+        streams.push(records.map(move |mut record| {
+            record.source = Some(name.clone());
+            record
+        }));
+    }
+
+    let result = run_monitors(MergedRequestIterator::new(streams, config), sink, config);
+    rejected.borrow().report();
+    result?;
+
+    match error.into_inner() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
 
-    log::debug!("validated headers");
+/// Reads request records from a live source, such as a followed file, runs monitors according
+/// to config, writing their output to sink as soon as it's available.
+///
+/// Source is read as described for [read_records], on a background thread, except that if the
+/// input format is detected, it's from the first line that can be parsed. While no records
+/// are arriving, the monitors are ticked every second so that stats chunks are still closed
+/// and alerts can recover. Time is measured from the latest record's timestamp, so this works
+/// for sources whose timestamps don't match our clock, such as replayed logs.
+///
+/// Records that can't be parsed are handled according to the config's on_error policy. By
+/// default they're logged and skipped, so that one bad line from a network peer or a log
+/// writer doesn't stop monitoring. Other errors end the run.
+pub fn monitor_live(
+    source: impl Read + Send + 'static,
+    sink: &mut impl Write,
+    config: &Config,
+) -> Result<(), Error> {
+    config.validate()?;
+
+    // A little slack so the reader can get ahead of the monitors without blocking.
+    let (sender, receiver) = mpsc::sync_channel(1024);
+
+    let reader_config = config.clone();
+    thread::spawn(move || {
+        let records = match open_records(source, &reader_config, true) {
+            Ok(records) => records,
+            Err(error) => {
+                let _ = sender.send(Err(error));
+                return;
+            }
+        };
+        for record in records {
+            // Records that fail to parse are left to the on_error policy.
+            let failed = matches!(record, Err(ref error) if !matches!(error, Error::Parse { .. }));
+            // If the receiver is gone, the monitors have stopped and we can too.
+            if sender.send(record).is_err() || failed {
+                return;
+            }
+        }
+    });
 
-    let mut monitors: Vec<Box<dyn Monitor>> = vec![
-        Box::new(ChunkedStatsMonitor::from_config(&config)),
-        Box::new(RollingAlertsMonitor::from_config(&config)),
-    ];
+    let mut runner = MonitorRunner::new(sink, config)?;
+    let mut buffer = SortingBuffer::new(config);
 
-    log::debug!("monitors (initial state): {:#?}", monitors);
+    // The latest record timestamp we've seen, and when we received it.
+    let mut latest: Option<(u32, Instant)> = None;
+    let mut next_tick = Instant::now() + TICK_INTERVAL;
 
-    let rows = reader.deserialize::<RequestRecord>();
+    let mut rejected = RejectedRecords::new(config.on_error.clone().unwrap_or(ErrorPolicy::Skip));
+    let mut error = None;
 
-    let ordered_records =
-        SortedRequestIterator::new(rows.map(|row| row.expect("row should be valid")), config);
+    loop {
+        let timeout = next_tick.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(record)) => {
+                if latest.is_none_or(|(date, _)| record.date > date) {
+                    latest = Some((record.date, Instant::now()));
+                }
+                if let Err(late) = buffer.push(record) {
+                    error = Some(late.into());
+                    break;
+                }
+                while let Some(record) = buffer.pop() {
+                    runner.push(record, buffer.stats())?;
+                }
+            }
+            Ok(Err(err)) => {
+                if let Err(err) = rejected.handle(None, err) {
+                    error = Some(err);
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
 
-    for record in ordered_records {
+        runner.sink.flush()?;
+
+        if Instant::now() < next_tick {
+            continue;
+        }
+        next_tick = Instant::now() + TICK_INTERVAL;
+
+        if let Some((date, received)) = latest {
+            let now = date + received.elapsed().as_secs() as u32;
+            let released_before = buffer.advance_to(now);
+            while let Some(record) = buffer.pop() {
+                runner.push(record, buffer.stats())?;
+            }
+            runner.tick(released_before)?;
+        }
+    }
+
+    buffer.finish();
+    while let Some(record) = buffer.pop() {
+        runner.push(record, buffer.stats())?;
+    }
+    runner.finish(buffer.stats())?;
+    rejected.report();
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Formats error with each of its sources, like "invalid record at line 2: missing field
+/// authuser".
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// Runs monitors according to config over records, writing their output to sink.
+///
+/// Records may be out-of-order by up to the config's maximum_timestamp_error.
+pub fn monitor_records(
+    records: impl Iterator<Item = RequestRecord>,
+    sink: &mut impl Write,
+    config: &Config,
+) -> Result<(), Error> {
+    config.validate()?;
+
+    run_monitors(SortedRequestIterator::new(records, config), sink, config)
+}
+
+/// Runs monitors according to config over chronologically-ordered records, writing their
+/// output to sink.
+fn run_monitors(
+    mut ordered_records: impl SortedRecords,
+    sink: &mut impl Write,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut runner = MonitorRunner::new(sink, config)?;
+
+    while let Some(record) = ordered_records.next() {
+        runner.push(record, ordered_records.stats())?;
+    }
+
+    // Even when a late record stopped the run, the records that were sorted and released
+    // before it are still reported. Those still buffered for sorting, from any input, aren't.
+    runner.finish(ordered_records.stats())?;
+
+    match ordered_records.take_error() {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// The monitors we run, with where their output goes.
+#[derive(Debug)]
+struct MonitorRunner<'a, W: Write> {
+    monitors: Vec<Box<dyn Monitor>>,
+    sink: &'a mut W,
+    config: &'a Config,
+    /// The metrics we're serving, if the config has a metrics address.
+    metrics: Option<Arc<Mutex<Metrics>>>,
+    /// How requests are grouped into sections, found once per record for the monitors and
+    /// metrics.
+    sections: Sections,
+    /// The exporters that stats are pushed to.
+    exporters: Vec<Box<dyn Exporter>>,
+    /// The notifiers that alert state changes are sent to.
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl<'a, W: Write> MonitorRunner<'a, W> {
+    /// Creates each of the monitors according to config, and starts serving metrics if the
+    /// config has a metrics address.
+    fn new(sink: &'a mut W, config: &'a Config) -> Result<Self, Error> {
+        let monitors: Vec<Box<dyn Monitor>> = vec![
+            Box::new(ChunkedStatsMonitor::from_config(config)),
+            Box::new(RollingAlertsMonitor::from_config(config)),
+            Box::new(UserAlertsMonitor::from_config(config)),
+        ];
+
+        log::debug!("monitors (initial state): {:#?}", monitors);
+
+        let metrics = match config.metrics_address {
+            Some(addr) => {
+                let server = MetricsServer::bind(addr).map_err(|source| Error::Setup {
+                    name: format!("metrics server on {}", addr),
+                    source,
+                })?;
+                Some(server.metrics().clone())
+            }
+            None => None,
+        };
+
+        let exporters = config.exporters.create().map_err(|source| Error::Setup {
+            name: "exporters".to_string(),
+            source,
+        })?;
+
+        let notifiers = config.notifiers.create()?;
+
+        Ok(Self {
+            monitors,
+            sink,
+            config,
+            metrics,
+            sections: Sections::new(&config.sections),
+            exporters,
+            notifiers,
+        })
+    }
+
+    /// Pushes record into each of the monitors, writing their output to sink.
+    fn push(&mut self, record: RequestRecord, sorting_stats: SortingStats) -> Result<(), Error> {
         let record = Rc::new(record);
+        let section = self.sections.section(&record);
+
+        for monitor in self.monitors.iter_mut() {
+            let events = monitor.push_in_section(&record, section.as_deref())?;
+            export(&mut self.exporters, &events);
+            notify(&mut self.notifiers, &events);
+            write_events(self.sink, events, self.config)?;
+        }
+
+        for notifier in self.notifiers.iter_mut() {
+            notifier.tick();
+        }
+
+        self.update_metrics(|metrics| {
+            metrics.count(&record, section.as_deref());
+            metrics.sorting = sorting_stats;
+        });
+
+        Ok(())
+    }
+
+    /// Ticks each of the monitors, writing their output to sink.
+    fn tick(&mut self, now: u32) -> Result<(), Error> {
+        for monitor in self.monitors.iter_mut() {
+            let events = monitor.tick(now)?;
+            export(&mut self.exporters, &events);
+            notify(&mut self.notifiers, &events);
+            write_events(self.sink, events, self.config)?;
+        }
+
+        for notifier in self.notifiers.iter_mut() {
+            notifier.tick();
+        }
+
+        self.update_metrics(|_| {});
+
+        Ok(self.sink.flush()?)
+    }
+
+    /// Writes any pending output from the monitors to sink, after the last record, then waits
+    /// for notifications to be delivered and reports any late records.
+    fn finish(self, sorting_stats: SortingStats) -> Result<(), Error> {
+        let mut exporters = self.exporters;
+        let mut notifiers = self.notifiers;
+        for mut monitor in self.monitors {
+            let events = monitor.pending()?;
+            export(&mut exporters, &events);
+            notify(&mut notifiers, &events);
+            write_events(self.sink, events, self.config)?;
+
+            log::debug!("monitor (final state): {:#?}", monitor);
+        }
+
+        self.sink.flush()?;
+
+        for notifier in notifiers {
+            notifier.finish();
+        }
+
+        report_late_records(sorting_stats, &self.config.late_policy);
 
-        for monitor in monitors.iter_mut() {
-            let output = monitor.push(&record)?;
-            for line in output {
-                writeln!(sink, "{}", &line)?;
+        Ok(())
+    }
+
+    /// Updates the metrics we're serving, if any, with update and then the monitors' state.
+    fn update_metrics(&self, update: impl FnOnce(&mut Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().expect("metrics lock shouldn't be poisoned");
+            update(&mut metrics);
+            for monitor in &self.monitors {
+                monitor.update_metrics(&mut metrics);
             }
         }
     }
+}
 
-    for monitor in monitors.iter_mut() {
-        let output = monitor.pending()?;
-        for line in output {
-            writeln!(sink, "{}", &line)?;
+/// Pushes the stats from events to each of the exporters.
+///
+/// Failures are logged rather than returned, so that an unavailable metrics system doesn't
+/// stop us from monitoring.
+fn export(exporters: &mut [Box<dyn Exporter>], events: &[MonitorEvent]) {
+    for event in events {
+        if let MonitorEvent::Stats(stats) = event {
+            for exporter in exporters.iter_mut() {
+                if let Err(error) = exporter.export(stats) {
+                    log::warn!("failed to export stats to {}: {}", exporter.name(), error);
+                }
+            }
         }
     }
+}
+
+/// Logs a summary of the records that were too far out of order to be sorted, if any.
+fn report_late_records(stats: SortingStats, policy: &LatePolicy) {
+    if stats.late == 0 {
+        return;
+    }
+
+    let handled = match policy {
+        LatePolicy::Drop => format!("{} were dropped", stats.dropped),
+        LatePolicy::Clamp => format!("{} had their timestamps clamped", stats.clamped),
+        LatePolicy::Stream(path) => {
+            format!("{} were written to {}", stats.streamed, path.display())
+        }
+        LatePolicy::Fail => "the run stopped at the first late record".to_string(),
+    };
+    log::warn!(
+        "{} of {} records were too far out of order to be sorted, up to {} seconds late, and {}; a larger --maximum-timestamp-error may help",
+        stats.late,
+        stats.read,
+        stats.max_delay,
+        handled
+    );
+}
 
-    log::debug!("monitors (final state): {:#?}", monitors);
+/// Queues notifications of the alert state changes in events with each of the notifiers.
+fn notify(notifiers: &mut [Box<dyn Notifier>], events: &[MonitorEvent]) {
+    let alerts = events
+        .iter()
+        .filter(|event| !matches!(event, MonitorEvent::Stats(_)));
+    for event in alerts {
+        for notifier in notifiers.iter_mut() {
+            notifier.notify(event);
+        }
+    }
+}
 
+/// Writes events to sink as lines in the config's output format.
+fn write_events(
+    sink: &mut impl Write,
+    events: Vec<MonitorEvent>,
+    config: &Config,
+) -> Result<(), Error> {
+    for event in events {
+        for line in config.output_format.render(&event) {
+            writeln!(sink, "{}", &line)?;
+        }
+    }
     Ok(())
 }
@@ -1,8 +1,27 @@
 //! Binary entry point for http-monitor.
 
+use std::{
+    fs::File,
+    io::Read,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use http_monitor::{
+    AlertmanagerConfig, FollowReader, GraphiteConfig, InfluxDbConfig, StatsdConfig, SyslogListener,
+    WebhookConfig,
+};
+
 #[derive(argh::FromArgs, Debug)]
-/// Runs monitors against a CSV stream of HTTP request records from stdin.
+/// Runs monitors against streams of HTTP request records from files or stdin.
 struct Args {
+    /// the files to read request records from, which may be compressed with gzip, zstd or bzip2.
+    /// several files are merged chronologically. reads from stdin if none are specified.
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+
     /// enables maximum logging for our code and debug logging for dependencies. overrides
     /// RUST_LOG.
     #[argh(switch, short = 'v')]
@@ -12,17 +31,135 @@ struct Args {
     #[argh(switch, short = 'q')]
     quiet: bool,
 
+    /// load settings from this TOML, JSON or YAML config file. other options override the
+    /// file's settings.
+    #[argh(option)]
+    config: Option<PathBuf>,
+
     /// the average number of requests per second required to trigger an alert.
     #[argh(option)]
     alert_rate: Option<u32>,
 
+    /// the average number of requests per second from a single authenticated user required
+    /// to trigger an alert for that user. implies --keep-auth-user.
+    #[argh(option)]
+    user_alert_rate: Option<u32>,
+
+    /// keep the authenticated user of each request, which is discarded by default.
+    #[argh(switch)]
+    keep_auth_user: bool,
+
     /// the number of seconds over which the request count is averaged for alerting.
     #[argh(option)]
     alert_window: Option<u32>,
 
+    /// the number of leading path segments in a section, like 2 for /api/user. defaults to 1.
+    #[argh(option)]
+    section_depth: Option<u32>,
+
+    /// replace numeric ids, uuids and hashes in request paths with placeholders like {{id}}.
+    /// only segments within the section depth are kept, so with --section-depth 3,
+    /// /users/12345/orders is counted as /users/{{id}}/orders.
+    #[argh(switch)]
+    template_paths: bool,
+
     /// the number of seconds worth of requests to aggregate for each stats output.
     #[argh(option)]
     stats_window: Option<u32>,
+
+    /// the number of seconds that records' timestamps may be out of order, or "auto" to
+    /// measure it over a warm-up period. records later than this are handled according to
+    /// --late-records.
+    #[argh(option)]
+    maximum_timestamp_error: Option<TimestampError>,
+
+    /// what to do with records that are further out of order than the maximum timestamp
+    /// error: drop them, clamp their timestamps into order (the default), stream=PATH to
+    /// append them to a file as json, or fail.
+    #[argh(option)]
+    late_records: Option<http_monitor::LatePolicy>,
+
+    /// what to do with records that can't be parsed: skip them, logging each one, fail, or
+    /// dead-letter=PATH to skip them and append each one's position and error to a file as
+    /// json. fails by default, or skips when following or listening.
+    #[argh(option)]
+    on_error: Option<http_monitor::ErrorPolicy>,
+
+    /// the format of the input: csv, common (Common Log Format), combined (Combined Log
+    /// Format) or json (JSON Lines). detected from the first few lines if not specified.
+    #[argh(option)]
+    format: Option<http_monitor::InputFormat>,
+
+    /// keep reading the input file as it's appended to, like `tail -F`, following it through
+    /// log rotation, and output stats and alerts even while no requests arrive. requires a
+    /// single input file, or treats stdin as a live stream if there isn't one.
+    #[argh(switch, short = 'f')]
+    follow: bool,
+
+    /// the format to write monitor output in: text, or json (JSON Lines, one object per stats
+    /// window or alert state change).
+    #[argh(option)]
+    output: Option<http_monitor::OutputFormat>,
+
+    /// serve Prometheus metrics over http on this address, such as 127.0.0.1:9184, at
+    /// /metrics.
+    #[argh(option)]
+    metrics_address: Option<SocketAddr>,
+
+    /// send stats for each window to StatsD over udp at this host and port, such as
+    /// 127.0.0.1:8125. overrides only the address of the config file's statsd exporter.
+    #[argh(option)]
+    statsd: Option<String>,
+
+    /// send stats for each window to Graphite over tcp at this host and port, such as
+    /// 127.0.0.1:2003. overrides only the address of the config file's graphite exporter.
+    #[argh(option)]
+    graphite: Option<String>,
+
+    /// send stats for each window as InfluxDB line protocol over udp to this host and port,
+    /// such as 127.0.0.1:8089. overrides only the address of the config file's influxdb
+    /// exporter.
+    #[argh(option)]
+    influxdb: Option<String>,
+
+    /// POST each alert and recovery as json to this url, retrying if it fails. may be
+    /// repeated, and adds to the config file's webhooks.
+    #[argh(option)]
+    webhook: Vec<String>,
+
+    /// post each alert to Prometheus Alertmanager at this url, such as
+    /// http://127.0.0.1:9093, as firing when it's triggered and resolved when it recovers.
+    /// may be repeated, and adds to the config file's alertmanagers.
+    #[argh(option)]
+    alertmanager: Vec<String>,
+
+    /// listen for syslog messages over udp and tcp on this address, such as 127.0.0.1:5514,
+    /// instead of reading input files. each message's content is parsed as a log line.
+    #[argh(option)]
+    listen: Option<SocketAddr>,
+}
+
+/// A value for --maximum-timestamp-error.
+#[derive(Debug)]
+enum TimestampError {
+    Seconds(u32),
+    Auto,
+}
+
+impl FromStr for TimestampError {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(TimestampError::Auto),
+            seconds => seconds.parse().map(TimestampError::Seconds).map_err(|_| {
+                format!(
+                    "expected a number of seconds or \"auto\", but got {:?}",
+                    seconds
+                )
+            }),
+        }
+    }
 }
 
 pub fn main() -> anyhow::Result<()> {
@@ -37,15 +174,21 @@ pub fn main() -> anyhow::Result<()> {
     }
     env_logger::try_init()?;
 
-    let mut config = http_monitor::Config::default();
+    let mut config = match &args.config {
+        Some(path) => http_monitor::Config::load(path)?,
+        None => http_monitor::Config::default(),
+    };
 
     // If stdin is a terminal, the user is probably confused. Bail with instructions.
-    if atty::is(atty::Stream::Stdin) {
+    if args.inputs.is_empty() && args.listen.is_none() && atty::is(atty::Stream::Stdin) {
         log::error!(
             "stdin must be a stream or file, not a terminal.
             
             example usage:
                 cargo run < samples/input.csv
+                cargo run -- samples/input.csv
+                cargo run -- web-1/access.log web-2/access.log
+                cargo run -- --listen 127.0.0.1:5514
             
             or with a release binary:
                 cargo build --release
@@ -60,17 +203,136 @@ pub fn main() -> anyhow::Result<()> {
         config.alert_rate = alert_rate;
     }
 
+    if let Some(user_alert_rate) = args.user_alert_rate {
+        config.user_alert_rate = Some(user_alert_rate);
+        config.keep_auth_user = true;
+    }
+
+    if args.keep_auth_user {
+        config.keep_auth_user = true;
+    }
+
     if let Some(alert_window) = args.alert_window {
         config.alert_window = alert_window;
     }
 
+    if let Some(section_depth) = args.section_depth {
+        config.sections.depth = section_depth;
+    }
+
+    if args.template_paths {
+        config.sections.detectors = http_monitor::SegmentDetector::ALL.to_vec();
+    }
+
     if let Some(stats_window) = args.stats_window {
         config.stats_window = stats_window;
     }
 
+    match args.maximum_timestamp_error {
+        Some(TimestampError::Seconds(seconds)) => {
+            config.maximum_timestamp_error = seconds;
+            config.timestamp_error_tuning = None;
+        }
+        Some(TimestampError::Auto) => {
+            config
+                .timestamp_error_tuning
+                .get_or_insert_with(Default::default);
+        }
+        None => {}
+    }
+
+    if let Some(late_policy) = args.late_records {
+        config.late_policy = late_policy;
+    }
+
+    if let Some(on_error) = args.on_error {
+        config.on_error = Some(on_error);
+    }
+
+    if let Some(format) = args.format {
+        config.input_format = Some(format);
+    }
+
+    if let Some(output) = args.output {
+        config.output_format = output;
+    }
+
+    if let Some(metrics_address) = args.metrics_address {
+        config.metrics_address = Some(metrics_address);
+    }
+
+    // Only the address is overridden, keeping any other exporter settings from the file.
+    if let Some(address) = &args.statsd {
+        match &mut config.exporters.statsd {
+            Some(statsd) => statsd.address = address.clone(),
+            None => config.exporters.statsd = Some(StatsdConfig::new(address)),
+        }
+    }
+
+    if let Some(address) = &args.graphite {
+        match &mut config.exporters.graphite {
+            Some(graphite) => graphite.address = address.clone(),
+            None => config.exporters.graphite = Some(GraphiteConfig::new(address)),
+        }
+    }
+
+    if let Some(address) = &args.influxdb {
+        match &mut config.exporters.influxdb {
+            Some(influxdb) => influxdb.address = address.clone(),
+            None => config.exporters.influxdb = Some(InfluxDbConfig::new(address)),
+        }
+    }
+
+    for url in &args.webhook {
+        config.notifiers.webhooks.push(WebhookConfig::new(url));
+    }
+
+    for url in &args.alertmanager {
+        config
+            .notifiers
+            .alertmanagers
+            .push(AlertmanagerConfig::new(url));
+    }
+
+    config.validate()?;
+
     log::debug!("{:#?}", &config);
 
-    http_monitor::monitor_stream(&mut std::io::stdin(), &mut std::io::stdout(), &config)?;
+    if let Some(addr) = args.listen {
+        if args.follow || !args.inputs.is_empty() {
+            bail!("--listen can't be combined with input files or --follow");
+        }
+        let listener =
+            SyslogListener::bind(addr).with_context(|| format!("failed to listen on {}", addr))?;
+        http_monitor::monitor_live(listener, &mut std::io::stdout(), &config)?;
+        return Ok(());
+    }
+
+    match &args.inputs[..] {
+        [path] if args.follow => {
+            let reader = FollowReader::open(path)
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            http_monitor::monitor_live(reader, &mut std::io::stdout(), &config)?;
+        }
+        [] if args.follow => {
+            http_monitor::monitor_live(std::io::stdin(), &mut std::io::stdout(), &config)?;
+        }
+        _ if args.follow => bail!("--follow accepts at most one input file"),
+        [] => {
+            http_monitor::monitor_stream(&mut std::io::stdin(), &mut std::io::stdout(), &config)?;
+        }
+        [path] => {
+            http_monitor::monitor_stream(&mut open(path)?, &mut std::io::stdout(), &config)?;
+        }
+        paths => {
+            let mut sources = Vec::new();
+            for path in paths {
+                let source: Box<dyn Read> = Box::new(open(path)?);
+                sources.push((path.display().to_string(), source));
+            }
+            http_monitor::monitor_streams(sources, &mut std::io::stdout(), &config)?;
+        }
+    }
 
     log::info!("done");
 
@@ -84,3 +346,7 @@ pub fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn open(path: &Path) -> anyhow::Result<File> {
+    File::open(path).with_context(|| format!("failed to open {}", path.display()))
+}
@@ -0,0 +1,191 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    iter::FusedIterator,
+};
+
+use crate::{
+    sorted_request_iterator::SortedRecords, Config, LateRecordError, RequestRecord,
+    SortedRequestIterator, SortingStats,
+};
+
+/// Merges several iterators of RequestRecords into a single chronological iterator.
+///
+/// Each source is wrapped in its own SortedRequestIterator, so each may be out-of-order by up
+/// to the config's maximum_timestamp_error, and the sorted sources are then merged by taking
+/// whichever has the earliest next record. Records with equal timestamps are taken from the
+/// sources in the order that the sources were given. If a source fails because of a late
+/// record, iteration ends with the error available from [MergedRequestIterator::error], and
+/// the records still buffered in the other sources are discarded.
+#[derive(Debug)]
+pub struct MergedRequestIterator<T: Iterator<Item = RequestRecord>> {
+    /// The sorted sources being merged.
+    sources: Vec<SortedRequestIterator<T>>,
+    /// The next record from each source that isn't exhausted, with the earliest at the top.
+    next: BinaryHeap<Reverse<SourceRecord>>,
+    /// The error from a source that ended iteration, if any.
+    error: Option<LateRecordError>,
+}
+
+/// A request record wrapped to sort by its date then (for stability) the index of its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SourceRecord {
+    record: RequestRecord,
+    source: usize,
+}
+
+impl PartialOrd for SourceRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SourceRecord {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.record.date, self.source).cmp(&(other.record.date, other.source))
+    }
+}
+
+impl<T: Iterator<Item = RequestRecord>> MergedRequestIterator<T> {
+    /// Wraps each of the source iterators, buffering each according to the config's
+    /// maximum_timestamp_error.
+    pub fn new(sources: impl IntoIterator<Item = T>, config: &Config) -> Self {
+        let mut sources: Vec<_> = sources
+            .into_iter()
+            .map(|source| SortedRequestIterator::new(source, config))
+            .collect();
+
+        let next = sources
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(source, records)| {
+                records
+                    .next()
+                    .map(|record| Reverse(SourceRecord { record, source }))
+            })
+            .collect();
+
+        let error = sources.iter_mut().find_map(|source| source.take_error());
+
+        Self {
+            sources,
+            next,
+            error,
+        }
+    }
+
+    /// Counts of the records that have been read from all of the sources.
+    pub fn stats(&self) -> SortingStats {
+        self.sources
+            .iter()
+            .map(|source| source.stats())
+            .fold(SortingStats::default(), |total, stats| total + stats)
+    }
+
+    /// The error from a late record in a source that ended iteration, if any.
+    pub fn error(&self) -> Option<&LateRecordError> {
+        self.error.as_ref()
+    }
+}
+
+impl<T: Iterator<Item = RequestRecord>> SortedRecords for MergedRequestIterator<T> {
+    fn stats(&self) -> SortingStats {
+        MergedRequestIterator::stats(self)
+    }
+
+    fn take_error(&mut self) -> Option<LateRecordError> {
+        self.error.take()
+    }
+}
+
+impl<T: Iterator<Item = RequestRecord>> Iterator for MergedRequestIterator<T> {
+    type Item = RequestRecord;
+
+    fn next(&mut self) -> Option<RequestRecord> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        let Reverse(SourceRecord { record, source }) = self.next.pop()?;
+
+        match self.sources[source].next() {
+            Some(next) => self.next.push(Reverse(SourceRecord {
+                record: next,
+                source,
+            })),
+            None => {
+                self.error = self.sources[source].take_error();
+                // Records can't be merged in order without this source, so we stop.
+                if self.error.is_some() {
+                    self.next.clear();
+                }
+            }
+        }
+
+        Some(record)
+    }
+}
+
+impl<T: Iterator<Item = RequestRecord>> FusedIterator for MergedRequestIterator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn record(date: u32, request: &str) -> RequestRecord {
+        RequestRecord {
+            remote_host: Ipv4Addr::LOCALHOST.into(),
+            rfc931: (),
+            auth_user: None,
+            date,
+            request: request.into(),
+            status: 200,
+            bytes: 0,
+            referer: None,
+            user_agent: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_empty_iter() {
+        let results: Vec<_> =
+            MergedRequestIterator::new(Vec::<std::vec::IntoIter<_>>::new(), &Config::default())
+                .collect();
+        assert_eq!(results, vec![]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = vec![
+            record(10, "a"),
+            record(12, "a"),
+            record(11, "a"),
+            record(20, "a"),
+        ];
+        let b = vec![record(9, "b"), record(11, "b"), record(30, "b")];
+        let c = vec![];
+
+        let results: Vec<_> = MergedRequestIterator::new(
+            vec![a.into_iter(), b.into_iter(), c.into_iter()],
+            &Config::default(),
+        )
+        .map(|record| (record.date, record.request.to_string()))
+        .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                (9, "b".to_string()),
+                (10, "a".to_string()),
+                (11, "a".to_string()),
+                (11, "b".to_string()),
+                (12, "a".to_string()),
+                (20, "a".to_string()),
+                (30, "b".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use itertools::Itertools;
+
+use crate::{RequestRecord, SortingStats};
+
+/// The most sections that requests are counted by, so that requests for many distinct paths
+/// can't grow the metrics without bound. Requests in any further sections are counted in the
+/// "other" section.
+const MAX_SECTIONS: usize = 100;
+
+/// How long to wait to read a request from, or write a response to, a metrics client, since
+/// clients are served one at a time.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Live metrics about the requests and monitors, which can be exposed to Prometheus.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metrics {
+    /// The number of requests by section and status code. Once there are [MAX_SECTIONS]
+    /// sections, requests in new ones are counted in the "other" section.
+    pub requests: BTreeMap<(String, u16), u64>,
+    /// The number of requests with a malformed request line, which have no section.
+    pub malformed_requests: u64,
+    /// The average request rate over the rolling alert window.
+    pub alert_average_rate: f64,
+    /// The average request rate that triggers the alert.
+    pub alert_threshold_rate: f64,
+    /// Whether the request rate alert is triggered.
+    pub alert_firing: bool,
+    /// The number of users whose request rate alert is triggered.
+    pub user_alerts_firing: u64,
+    /// Counts of records that have been sorted by timestamp.
+    pub sorting: SortingStats,
+}
+
+impl Metrics {
+    /// Counts a request that's been pushed into the monitors, in its section, or as malformed
+    /// if it has none.
+    pub fn count(&mut self, record: &RequestRecord, section: Option<&str>) {
+        let section = match section {
+            Some(section) => section.to_string(),
+            None => {
+                self.malformed_requests += 1;
+                return;
+            }
+        };
+
+        let mut key = (section, record.status);
+        if !self.requests.contains_key(&key)
+            && !self.has_section(&key.0)
+            && self.requests.keys().dedup_by(|a, b| a.0 == b.0).count() >= MAX_SECTIONS
+        {
+            key.0 = "other".to_string();
+        }
+        *self.requests.entry(key).or_insert(0) += 1;
+    }
+
+    /// Whether any requests have been counted in section.
+    fn has_section(&self, section: &str) -> bool {
+        let section = section.to_string();
+        self.requests
+            .range((section.clone(), 0)..=(section, u16::MAX))
+            .next()
+            .is_some()
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(output, "# HELP http_monitor_{} {}", name, help);
+            let _ = writeln!(output, "# TYPE http_monitor_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(output, "http_monitor_{}{} {}", name, labels, value);
+            }
+        };
+
+        metric(
+            "requests_total",
+            "counter",
+            "Requests read, by section and status code.",
+            self.requests
+                .iter()
+                .map(|((section, status), count)| {
+                    (
+                        format!(
+                            "{{section=\"{}\",status=\"{}\"}}",
+                            escape_label(section),
+                            status
+                        ),
+                        count.to_string(),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            "malformed_requests_total",
+            "counter",
+            "Requests read with a malformed request line, which aren't counted by section.",
+            vec![(String::new(), self.malformed_requests.to_string())],
+        );
+        metric(
+            "alert_average_rate",
+            "gauge",
+            "Average requests per second over the rolling alert window.",
+            vec![(String::new(), self.alert_average_rate.to_string())],
+        );
+        metric(
+            "alert_threshold_rate",
+            "gauge",
+            "Average requests per second over the rolling alert window that triggers the alert.",
+            vec![(String::new(), self.alert_threshold_rate.to_string())],
+        );
+        metric(
+            "alert_firing",
+            "gauge",
+            "Whether the request rate alert is triggered (1) or not (0).",
+            vec![(String::new(), (self.alert_firing as u8).to_string())],
+        );
+        metric(
+            "user_alerts_firing",
+            "gauge",
+            "The number of authenticated users whose request rate alert is triggered.",
+            vec![(String::new(), self.user_alerts_firing.to_string())],
+        );
+        metric(
+            "records_read_total",
+            "counter",
+            "Records read from input, before sorting them by timestamp.",
+            vec![(String::new(), self.sorting.read.to_string())],
+        );
+        metric(
+            "records_reordered_total",
+            "counter",
+            "Records that were read out of order, and sorted back into place.",
+            vec![(String::new(), self.sorting.reordered.to_string())],
+        );
+        metric(
+            "records_late_total",
+            "counter",
+            "Records that were read too far out of order to be sorted into place.",
+            vec![(String::new(), self.sorting.late.to_string())],
+        );
+        metric(
+            "late_records_total",
+            "counter",
+            "Late records, by how they were handled according to the late record policy.",
+            [
+                ("dropped", self.sorting.dropped),
+                ("clamped", self.sorting.clamped),
+                ("streamed", self.sorting.streamed),
+            ]
+            .iter()
+            .map(|(action, count)| (format!("{{action=\"{}\"}}", action), count.to_string()))
+            .collect(),
+        );
+
+        output
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// A minimal HTTP server exposing metrics to Prometheus, at `/metrics`.
+#[derive(Debug)]
+pub struct MetricsServer {
+    /// The address that the server is listening on.
+    local_addr: SocketAddr,
+    /// The metrics being served, which may be updated while the server is running.
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsServer {
+    /// Starts serving metrics on addr, on a background thread.
+    ///
+    /// If addr has port 0, an available port is chosen.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+
+        log::info!("serving metrics at http://{}/metrics", local_addr);
+
+        let served = metrics.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &served));
+                if let Err(error) = result {
+                    log::warn!("failed to serve metrics request: {}", error);
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            metrics,
+        })
+    }
+
+    /// The address that the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The metrics being served, to be updated.
+    pub fn metrics(&self) -> &Arc<Mutex<Metrics>> {
+        &self.metrics
+    }
+}
+
+/// Reads an HTTP request from stream and responds with the metrics.
+///
+/// Any GET request for `/metrics` (or `/`) gets the metrics, and anything else gets a 404.
+fn respond(stream: TcpStream, metrics: &Mutex<Metrics>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // We don't need any of the headers, but we read them so the client isn't confused by the
+    // connection closing before it's finished sending.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) | (Some("GET"), Some("/")) => (
+            "200 OK",
+            metrics
+                .lock()
+                .expect("metrics lock shouldn't be poisoned")
+                .to_prometheus(),
+        ),
+        _ => ("404 Not Found", "not found, try /metrics\n".to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{Ipv4Addr, Shutdown},
+    };
+
+    use super::*;
+    use crate::models::tests::record;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let server = MetricsServer::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        {
+            let mut metrics = server.metrics().lock().unwrap();
+            metrics.requests.insert(("/api".to_string(), 200), 3);
+            metrics.requests.insert(("/a\"b".to_string(), 404), 1);
+            metrics.malformed_requests = 2;
+            metrics.alert_average_rate = 1.5;
+            metrics.alert_firing = true;
+            metrics.sorting.read = 4;
+            metrics.sorting.clamped = 2;
+        }
+
+        let response = get(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\n# TYPE http_monitor_requests_total counter\n"));
+        assert!(
+            response.contains("\nhttp_monitor_requests_total{section=\"/api\",status=\"200\"} 3\n")
+        );
+        assert!(response
+            .contains("\nhttp_monitor_requests_total{section=\"/a\\\"b\",status=\"404\"} 1\n"));
+        assert!(response.contains("\nhttp_monitor_malformed_requests_total 2\n"));
+        assert!(response.contains("\nhttp_monitor_alert_average_rate 1.5\n"));
+        assert!(response.contains("\nhttp_monitor_alert_firing 1\n"));
+        assert!(response.contains("\nhttp_monitor_records_read_total 4\n"));
+        assert!(response.contains("\nhttp_monitor_late_records_total{action=\"clamped\"} 2\n"));
+
+        assert!(get(server.local_addr(), "/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_section_limit() {
+        let mut metrics = Metrics::default();
+        for i in 0..MAX_SECTIONS + 2 {
+            metrics.count(&record("GET / HTTP/1.0"), Some(&format!("/{}", i)));
+        }
+        // Requests in sections that are already counted still are.
+        metrics.count(&record("GET / HTTP/1.0"), Some("/0"));
+
+        assert_eq!(metrics.requests.len(), MAX_SECTIONS + 1);
+        assert_eq!(metrics.requests[&("/0".to_string(), 200)], 2);
+        assert_eq!(metrics.requests[&("other".to_string(), 200)], 2);
+    }
+}
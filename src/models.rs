@@ -1,39 +1,310 @@
-use std::{fmt::Debug, net::Ipv4Addr, str};
+use std::{
+    fmt::{self, Debug},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::{self, FromStr},
+};
 
+use chrono::NaiveDateTime;
+use serde::{de, ser::SerializeSeq, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use string_cache::DefaultAtom;
+
+use crate::{
+    CsvColumns, ErrorPolicy, Exporters, InputFormat, JsonFields, LatePolicy, Notifiers,
+    OutputFormat, ParseError, SectionConfig, TimestampErrorTuning,
+};
 
 /// HTTP request record from input.
+///
+/// Strings that repeat across many requests, like hostnames, users, user agents and HTTP
+/// methods, are interned as [DefaultAtom]s, so that each distinct value is only stored once.
 #[derive(Debug, Deserialize, Serialize, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct RequestRecord {
-    /// IP address that the request came from.
+    /// IP address or hostname that the request came from.
     #[serde(rename = "remotehost")]
-    pub remote_host: Ipv4Addr,
+    pub remote_host: RemoteHost,
     /// Unused, included for compatibility.
     #[serde(skip)]
     pub rfc931: (),
-    /// Unused, included for compatibility.
-    #[serde(skip, rename = "authuser")]
-    pub auth_user: (),
+    /// The authenticated user that made the request, if known. Only kept if the config's
+    /// keep_auth_user is set.
+    #[serde(default, rename = "authuser", skip_serializing_if = "Option::is_none")]
+    pub auth_user: Option<DefaultAtom>,
     /// Unix timestamp of request.
     pub date: u32,
     /// First line of the http request, with the method and path.
-    pub request: String,
+    pub request: RequestLine,
     /// HTTP status code of response.
     pub status: u16,
     /// Byte length of response.
     pub bytes: u64,
+    /// Referring page reported by the client, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referer: Option<DefaultAtom>,
+    /// User-Agent reported by the client, if known.
+    #[serde(default, rename = "useragent", skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<DefaultAtom>,
+    /// The name of the input that this record was read from, if there are several.
+    #[serde(skip)]
+    pub source: Option<DefaultAtom>,
+}
+
+/// The client that a request came from: an IPv4 or IPv6 address, or a hostname if the server
+/// resolved it.
+///
+/// Serialized as a string, like `10.0.0.2`, `2001:db8::2` or `client.example.com`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RemoteHost {
+    /// An IP address. IPv4-mapped IPv6 addresses are stored as IPv4.
+    Ip(IpAddr),
+    /// A hostname.
+    Name(DefaultAtom),
+}
+
+impl From<IpAddr> for RemoteHost {
+    fn from(address: IpAddr) -> Self {
+        RemoteHost::Ip(address.to_canonical())
+    }
+}
+
+impl From<Ipv4Addr> for RemoteHost {
+    fn from(address: Ipv4Addr) -> Self {
+        RemoteHost::Ip(address.into())
+    }
+}
+
+impl From<Ipv6Addr> for RemoteHost {
+    fn from(address: Ipv6Addr) -> Self {
+        IpAddr::from(address).into()
+    }
+}
+
+impl FromStr for RemoteHost {
+    type Err = ParseError;
+
+    /// Parses an IP address or hostname, ignoring a port suffix like Envoy's addresses include,
+    /// such as `10.0.0.2:5000` or `[2001:db8::2]:5000`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = value.parse::<IpAddr>() {
+            return Ok(address.into());
+        }
+        if let Ok(address) = value.parse::<SocketAddr>() {
+            return Ok(address.ip().into());
+        }
+
+        // Hostnames are letters, digits, hyphens and dots, optionally with a port. We require
+        // a letter so that malformed numeric addresses aren't mistaken for hostnames.
+        let name = match value.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => name,
+            _ => value,
+        };
+        let is_hostname = name.bytes().any(|b| b.is_ascii_alphabetic())
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_');
+        if is_hostname {
+            Ok(RemoteHost::Name(DefaultAtom::from(
+                name.to_ascii_lowercase(),
+            )))
+        } else {
+            Err(ParseError::InvalidField {
+                field: "remotehost",
+                value: value.to_string(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for RemoteHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteHost::Ip(address) => fmt::Display::fmt(address, f),
+            RemoteHost::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl serde::Serialize for RemoteHost {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RemoteHost {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <std::borrow::Cow<str> as serde::Deserialize>::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+/// The first line of an http request, parsed into its method, path, query string and protocol
+/// version.
+///
+/// Serialized as the line, like `GET /api/user?id=1 HTTP/1.1`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RequestLine {
+    /// A request line with a method and a target, and usually a protocol version.
+    Parsed {
+        /// The HTTP method, like `GET`.
+        method: DefaultAtom,
+        /// The scheme and authority of an absolute URL target, as sent to proxies, like
+        /// `http://example.com`.
+        origin: Option<String>,
+        /// The path of the request target, without the origin or query string. May only be
+        /// empty if there's an origin, which [RequestLine::path] treats as `/`.
+        path: String,
+        /// The query string, without the `?`, if the target has one.
+        query: Option<String>,
+        /// The protocol version, like `HTTP/1.1`, if there is one. HTTP/0.9 requests don't
+        /// include it.
+        protocol: Option<DefaultAtom>,
+    },
+    /// A line that isn't a valid request line, such as `-` or the start of a TLS handshake,
+    /// as servers log when a client sends garbage.
+    Malformed(String),
+}
+
+impl RequestLine {
+    /// Parses a request line, like `GET /api/user HTTP/1.0`.
+    pub fn parse(line: &str) -> Self {
+        Self::parse_parts(line).unwrap_or_else(|| RequestLine::Malformed(line.to_string()))
+    }
+
+    fn parse_parts(line: &str) -> Option<Self> {
+        let mut parts = line.split(' ');
+        let method = parts.next().filter(|method| {
+            !method.is_empty() && method.bytes().all(|b| b.is_ascii_alphabetic() || b == b'-')
+        })?;
+        let target = parts.next()?;
+        let protocol = parts.next();
+        if parts.next().is_some() || !protocol.is_none_or(|protocol| protocol.starts_with("HTTP/"))
+        {
+            return None;
+        }
+
+        // An absolute target has its scheme before any path or query, which may themselves
+        // contain "://", as in "/login?next=http://example.com/".
+        let scheme_end = target
+            .find("://")
+            .filter(|&scheme_end| !target[..scheme_end].contains(['/', '?']));
+        let (origin, target) = match scheme_end {
+            Some(scheme_end) => {
+                let path_start = target[scheme_end + 3..]
+                    .find(['/', '?'])
+                    .map_or(target.len(), |path_start| scheme_end + 3 + path_start);
+                let (origin, target) = target.split_at(path_start);
+                (Some(origin.to_string()), target)
+            }
+            None if target.starts_with('/') || target == "*" => (None, target),
+            None => return None,
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+
+        Some(RequestLine::Parsed {
+            method: DefaultAtom::from(method),
+            origin,
+            path: path.to_string(),
+            query,
+            protocol: protocol.map(DefaultAtom::from),
+        })
+    }
+
+    /// The HTTP method, if the line isn't malformed.
+    pub fn method(&self) -> Option<&DefaultAtom> {
+        match self {
+            RequestLine::Parsed { method, .. } => Some(method),
+            RequestLine::Malformed(_) => None,
+        }
+    }
+
+    /// The path of the request target, without the origin or query string, if the line isn't
+    /// malformed.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            RequestLine::Parsed { path, .. } if path.is_empty() => Some("/"),
+            RequestLine::Parsed { path, .. } => Some(path),
+            RequestLine::Malformed(_) => None,
+        }
+    }
+
+    /// The query string, if the line isn't malformed and its target has one.
+    pub fn query(&self) -> Option<&str> {
+        match self {
+            RequestLine::Parsed { query, .. } => query.as_deref(),
+            RequestLine::Malformed(_) => None,
+        }
+    }
+
+    /// The protocol version, if the line isn't malformed and includes one.
+    pub fn protocol(&self) -> Option<&DefaultAtom> {
+        match self {
+            RequestLine::Parsed { protocol, .. } => protocol.as_ref(),
+            RequestLine::Malformed(_) => None,
+        }
+    }
+}
+
+impl From<&str> for RequestLine {
+    fn from(line: &str) -> Self {
+        RequestLine::parse(line)
+    }
+}
+
+impl fmt::Display for RequestLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestLine::Parsed {
+                method,
+                origin,
+                path,
+                query,
+                protocol,
+            } => {
+                write!(f, "{} {}{}", method, origin.as_deref().unwrap_or(""), path)?;
+                if let Some(query) = query {
+                    write!(f, "?{}", query)?;
+                }
+                if let Some(protocol) = protocol {
+                    write!(f, " {}", protocol)?;
+                }
+                Ok(())
+            }
+            RequestLine::Malformed(line) => f.write_str(line),
+        }
+    }
+}
+
+impl serde::Serialize for RequestLine {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RequestLine {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let line = <std::borrow::Cow<str> as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(RequestLine::parse(&line))
+    }
 }
 
 impl RequestRecord {
-    pub fn section(&self) -> &str {
-        let path = self.request.split(' ').nth(1).unwrap_or("/unknown");
-        let section = path.split('/').nth(1).unwrap_or("unknown");
-        section
+    /// The first segment of the request path, without slashes, or None if the request line is
+    /// malformed. [Sections] finds configurable sections instead.
+    ///
+    /// [Sections]: crate::Sections
+    pub fn section(&self) -> Option<&str> {
+        let path = self.request.path()?;
+        let path = path.strip_prefix('/').unwrap_or(path);
+        path.split('/').next()
     }
 }
 
 /// Configuration for this log monitoring program.
 #[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// Number of seconds of log messages to aggregate for batch stats.
     /// This window is cleared every X seconds, each time stats are logged.
@@ -43,6 +314,312 @@ pub struct Config {
     pub alert_window: u32,
     /// Average number of requests per second required to trigger an alert.
     pub alert_rate: u32,
+    /// Whether to keep the authenticated user of each request, which is discarded by default
+    /// to save memory.
+    pub keep_auth_user: bool,
+    /// Average number of requests per second from a single authenticated user required to
+    /// trigger an alert for that user, over the same window as alert_rate. Requires
+    /// keep_auth_user. Users aren't alerted on if not set.
+    pub user_alert_rate: Option<u32>,
+    /// How request paths are grouped into sections, for stats and metrics.
+    pub sections: SectionConfig,
     /// The margin of error on a record's timestamp, in seconds.
     pub maximum_timestamp_error: u32,
+    /// How to choose the margin of error from the input, if it should be instead of using
+    /// maximum_timestamp_error.
+    pub timestamp_error_tuning: Option<TimestampErrorTuning>,
+    /// What to do with records that are further out of order than the margin of error.
+    pub late_policy: LatePolicy,
+    /// What to do with records that can't be parsed, or None to fail for input that's read to
+    /// the end and to skip them for live input.
+    pub on_error: Option<ErrorPolicy>,
+    /// The format of the input, or None to detect it from the first few lines.
+    pub input_format: Option<InputFormat>,
+    /// The columns to read each record field from, for CSV input.
+    pub csv_columns: CsvColumns,
+    /// The keys to read each record field from, for JSON Lines input.
+    pub json_fields: JsonFields,
+    /// The format to write monitor output in.
+    pub output_format: OutputFormat,
+    /// The address to serve Prometheus metrics on, if any, such as `127.0.0.1:9184`.
+    pub metrics_address: Option<SocketAddr>,
+    /// The external metrics systems to push the stats for each window to.
+    pub exporters: Exporters,
+    /// The external services to send alert state changes to.
+    pub notifiers: Notifiers,
+}
+
+/// An error describing each of the invalid settings in a [Config].
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[error("invalid config:{}", .fields.iter().map(|field| format!("\n  {}", field)).collect::<String>())]
+pub struct ConfigError {
+    /// The invalid settings.
+    pub fields: Vec<InvalidConfigField>,
+}
+
+/// A setting in a [Config] that has an invalid value.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[error("{key} {reason}")]
+pub struct InvalidConfigField {
+    /// The key of the setting, as in a config file, such as `exporters.statsd.address`.
+    pub key: String,
+    /// Why its value is invalid, such as "must be at least 1".
+    pub reason: String,
+}
+
+/// Output from a monitor, describing stats or a change in alert state.
+///
+/// Serialized as an object with a "type" of "stats", "alert_triggered" or "alert_recovered",
+/// and the fields of the variant. Timestamps are serialized as ISO 8601 strings in UTC, and
+/// breakdowns as arrays of objects with a "value" and a "count".
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MonitorEvent {
+    /// Stats for the requests in a window of time.
+    Stats(StatsWindow),
+    /// The average request rate has reached the alert threshold.
+    AlertTriggered(Alert),
+    /// The average request rate has fallen back below the alert threshold.
+    AlertRecovered(Alert),
+    /// The average request rate from a single user has reached the user alert threshold.
+    UserAlertTriggered(UserAlert),
+    /// The average request rate from a single user has fallen back below the user alert
+    /// threshold.
+    UserAlertRecovered(UserAlert),
+}
+
+/// Stats for the requests in a window of time.
+///
+/// Each breakdown is a list of values with the number of requests that had them, sorted with
+/// the most frequent first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsWindow {
+    /// Unix timestamp of the start of the window, inclusive.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub start: u32,
+    /// Unix timestamp of the end of the window, exclusive.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub end: u32,
+    /// The number of requests in the window.
+    pub request_count: u64,
+    /// The average number of requests per second through the window.
+    pub request_rate: f64,
+    /// The number of requests with a malformed request line, which aren't counted by section,
+    /// method or protocol.
+    pub malformed_count: u64,
+    /// Request counts by section, the leading segments of the request path with a leading
+    /// slash, as configured by the config's sections.
+    #[serde(serialize_with = "serialize_counts")]
+    pub sections: Vec<(String, u64)>,
+    /// Request counts by HTTP status code.
+    #[serde(serialize_with = "serialize_counts")]
+    pub status_codes: Vec<(u16, u64)>,
+    /// Request counts by section, for requests with a query string, to find the endpoints that
+    /// are queried most.
+    #[serde(serialize_with = "serialize_counts")]
+    pub query_sections: Vec<(String, u64)>,
+    /// Request counts by HTTP method.
+    #[serde(serialize_with = "serialize_counts")]
+    pub methods: Vec<(DefaultAtom, u64)>,
+    /// Request counts by protocol version, for requests that include it.
+    #[serde(serialize_with = "serialize_counts")]
+    pub protocols: Vec<(DefaultAtom, u64)>,
+    /// Request counts by user agent, for requests where it's known.
+    #[serde(serialize_with = "serialize_counts")]
+    pub user_agents: Vec<(DefaultAtom, u64)>,
+    /// Request counts by referring page, for requests where it's known.
+    #[serde(serialize_with = "serialize_counts")]
+    pub referers: Vec<(DefaultAtom, u64)>,
+    /// Request counts by the name of the input they were read from, if there are several.
+    #[serde(serialize_with = "serialize_counts")]
+    pub sources: Vec<(DefaultAtom, u64)>,
+}
+
+/// The state of the request rate alert when it was triggered or recovered.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// Unix timestamp of when the alert state changed.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub date: u32,
+    /// The number of requests in the rolling window.
+    pub request_count: u64,
+    /// The average number of requests per second through the window.
+    pub average_rate: f64,
+    /// The number of seconds in the rolling window.
+    pub window_seconds: u32,
+    /// The average number of requests per second that triggers the alert.
+    pub threshold_rate: u32,
+}
+
+/// The state of the request rate alert for a single authenticated user when it was triggered
+/// or recovered.
+///
+/// Serialized with the user alongside the fields of the alert.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserAlert {
+    /// The authenticated user whose requests are counted.
+    pub user: DefaultAtom,
+    /// The state of the alert, counting only this user's requests.
+    #[serde(flatten)]
+    pub alert: Alert,
+}
+
+pub(crate) fn serialize_timestamp<S: Serializer>(
+    timestamp: &u32,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let date = NaiveDateTime::from_timestamp((*timestamp).into(), 0);
+    serializer.collect_str(&date.format("%Y-%m-%dT%H:%M:%SZ"))
+}
+
+fn serialize_counts<S: Serializer, T: serde::Serialize>(
+    counts: &[(T, u64)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Count<'a, T> {
+        value: &'a T,
+        count: u64,
+    }
+
+    let mut seq = serializer.serialize_seq(Some(counts.len()))?;
+    for (value, count) in counts {
+        seq.serialize_element(&Count {
+            value,
+            count: *count,
+        })?;
+    }
+    seq.end()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    /// A record of a request with the given request line.
+    pub(crate) fn record(request: &str) -> RequestRecord {
+        RequestRecord {
+            remote_host: Ipv4Addr::new(10, 0, 0, 2).into(),
+            rfc931: (),
+            auth_user: None,
+            date: 1549573860,
+            request: RequestLine::parse(request),
+            status: 200,
+            bytes: 1234,
+            referer: None,
+            user_agent: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_request_line() {
+        let line = RequestLine::parse("GET /api/user?id=1&page=2 HTTP/1.1");
+        assert_eq!(line.method().map(|method| &**method), Some("GET"));
+        assert_eq!(line.path(), Some("/api/user"));
+        assert_eq!(line.query(), Some("id=1&page=2"));
+        assert_eq!(
+            line.protocol().map(|protocol| &**protocol),
+            Some("HTTP/1.1")
+        );
+        assert_eq!(line.to_string(), "GET /api/user?id=1&page=2 HTTP/1.1");
+
+        // Absolute URLs are reported by their path, but written out in full.
+        for absolute in &[
+            "GET http://example.com/report?x HTTP/1.0",
+            "GET http://example.com?x HTTP/1.0",
+            "GET http://example.com",
+        ] {
+            assert_eq!(RequestLine::parse(absolute).to_string(), *absolute);
+        }
+        let line = RequestLine::parse("GET http://example.com/report?x HTTP/1.0");
+        assert_eq!(line.path(), Some("/report"));
+        assert_eq!(line.query(), Some("x"));
+        assert_eq!(
+            RequestLine::parse("GET http://example.com").path(),
+            Some("/")
+        );
+
+        // A URL in the query doesn't make the target absolute.
+        let line = RequestLine::parse("GET /login?next=http://example.com/a/b HTTP/1.1");
+        assert_eq!(line.path(), Some("/login"));
+        assert_eq!(line.query(), Some("next=http://example.com/a/b"));
+        assert_eq!(
+            line.to_string(),
+            "GET /login?next=http://example.com/a/b HTTP/1.1"
+        );
+        assert_eq!(
+            RequestLine::parse("GET api?next=http://example.com HTTP/1.1"),
+            RequestLine::Malformed("GET api?next=http://example.com HTTP/1.1".to_string())
+        );
+
+        let line = RequestLine::parse("GET /");
+        assert_eq!(line.path(), Some("/"));
+        assert_eq!(line.protocol(), None);
+
+        for malformed in &[
+            "-",
+            "",
+            "GET",
+            "GET api/user HTTP/1.1",
+            "GET /api/user FTP/1.0",
+            "GET /api/user HTTP/1.1 extra",
+            "\\x16\\x03\\x01\\x00",
+        ] {
+            assert_eq!(
+                RequestLine::parse(malformed),
+                RequestLine::Malformed(malformed.to_string())
+            );
+        }
+
+        assert_eq!(
+            serde_json::to_string(&RequestLine::parse("POST /report HTTP/2.0")).unwrap(),
+            r#""POST /report HTTP/2.0""#
+        );
+    }
+
+    #[test]
+    fn test_section() {
+        let section = |request: &str| record(request).section().map(str::to_string);
+
+        assert_eq!(section("GET /api/user HTTP/1.0"), Some("api".to_string()));
+        assert_eq!(section("GET /api?id=1 HTTP/1.0"), Some("api".to_string()));
+        assert_eq!(section("GET / HTTP/1.0"), Some("".to_string()));
+        assert_eq!(section("-"), None);
+    }
+
+    #[test]
+    fn test_remote_host() {
+        let host = |text: &str| text.parse::<RemoteHost>();
+        let ipv6: Ipv6Addr = "2001:db8::2".parse().unwrap();
+
+        assert_eq!(host("10.0.0.2"), Ok(Ipv4Addr::new(10, 0, 0, 2).into()));
+        assert_eq!(host("10.0.0.2:5000"), Ok(Ipv4Addr::new(10, 0, 0, 2).into()));
+        assert_eq!(host("2001:db8::2"), Ok(ipv6.into()));
+        assert_eq!(host("[2001:db8::2]:5000"), Ok(ipv6.into()));
+        assert_eq!(
+            host("::ffff:10.0.0.2"),
+            Ok(Ipv4Addr::new(10, 0, 0, 2).into())
+        );
+        assert_eq!(
+            host("Client.Example.com:80"),
+            Ok(RemoteHost::Name("client.example.com".into()))
+        );
+        assert!(host("1234").is_err());
+        assert!(host("-").is_err());
+        assert!(host("").is_err());
+
+        let name = host("client.example.com").unwrap();
+        assert_eq!(name.to_string(), "client.example.com");
+        assert_eq!(
+            serde_json::to_string(&RemoteHost::from(ipv6)).unwrap(),
+            r#""2001:db8::2""#
+        );
+        assert_eq!(
+            serde_json::from_str(r#""client.example.com""#).ok(),
+            Some(name)
+        );
+    }
 }
@@ -1,15 +1,19 @@
-use std::{collections::HashMap, convert::TryInto, fmt::Debug, ops::Range, rc::Rc};
+use std::{collections::HashMap, fmt::Debug, ops::Range, rc::Rc};
 
-use chrono::NaiveDateTime;
 use itertools::Itertools;
+use string_cache::DefaultAtom;
 
-use crate::{Config, Monitor, RequestRecord};
+use crate::{Config, Error, Monitor, MonitorEvent, RequestRecord, Sections, StatsWindow};
 
+/// Monitor that aggregates requests into fixed-size chunks of time, and outputs stats for each.
 #[derive(Debug, Clone)]
 pub struct ChunkedStatsMonitor {
     /// The number of seconds of requests to include in each stats chunk.
     chunk_seconds: u32,
 
+    /// How requests are grouped into sections.
+    sections: Sections,
+
     /// Requests that are in the chunk currently being aggregated.
     requests: Vec<Rc<RequestRecord>>,
     /// The range of timestamps included in the pending chunk.
@@ -19,28 +23,54 @@ pub struct ChunkedStatsMonitor {
 
     /// Request counts for the current chunk.
     request_count: u64,
+    malformed_count: u64,
     requests_by_status_code: HashMap<u16, u64>,
     requests_by_section: HashMap<String, u64>,
+    queries_by_section: HashMap<String, u64>,
+    requests_by_method: HashMap<DefaultAtom, u64>,
+    requests_by_protocol: HashMap<DefaultAtom, u64>,
+    requests_by_user_agent: HashMap<DefaultAtom, u64>,
+    requests_by_referer: HashMap<DefaultAtom, u64>,
+    requests_by_source: HashMap<DefaultAtom, u64>,
 }
 
 impl ChunkedStatsMonitor {
-    fn maybe_flush_before(&mut self, record: &RequestRecord) -> anyhow::Result<Vec<String>> {
+    fn maybe_flush_before(&mut self, record: &RequestRecord) -> Result<Vec<MonitorEvent>, Error> {
         // If this is the first record we're seeing, use it for the starting time
         // of the first chunk.
-        let mut requests_time_range = self
+        let requests_time_range = self
             .requests_time_range
             .clone()
             .unwrap_or_else(|| record.date..(record.date + self.chunk_seconds));
 
+        self.flush_chunks_before(requests_time_range, record.date)
+    }
+
+    /// Outputs and clears every chunk from requests_time_range that ends at or before date.
+    ///
+    /// A record dated before the current chunk, which may happen if it arrives after we've
+    /// closed its own chunk on a tick, is counted in the current chunk instead.
+    fn flush_chunks_before(
+        &mut self,
+        mut requests_time_range: Range<u32>,
+        date: u32,
+    ) -> Result<Vec<MonitorEvent>, Error> {
         let mut output = Vec::new();
-        while !requests_time_range.contains(&record.date) {
+        while date >= requests_time_range.end {
             output.append(&mut self.pending()?);
 
             if !self.requests.is_empty() {
                 self.requests.clear();
                 self.request_count = 0;
+                self.malformed_count = 0;
                 self.requests_by_status_code.clear();
                 self.requests_by_section.clear();
+                self.queries_by_section.clear();
+                self.requests_by_method.clear();
+                self.requests_by_protocol.clear();
+                self.requests_by_user_agent.clear();
+                self.requests_by_referer.clear();
+                self.requests_by_source.clear();
             }
 
             requests_time_range =
@@ -51,21 +81,49 @@ impl ChunkedStatsMonitor {
 
         Ok(output)
     }
+
+    /// The values from counts with their counts, sorted with the most frequent first.
+    fn by_frequency<T: Ord + Clone>(counts: &HashMap<T, u64>) -> Vec<(T, u64)> {
+        counts
+            .iter()
+            .map(|(value, count)| (*count, value))
+            .sorted()
+            .rev()
+            .map(|(count, value)| (value.clone(), count))
+            .collect()
+    }
 }
 
 impl Monitor for ChunkedStatsMonitor {
     fn from_config(config: &Config) -> Self {
         Self {
             chunk_seconds: config.stats_window,
+            sections: Sections::new(&config.sections),
             requests: Vec::new(),
             requests_time_range: None,
             request_count: 0,
+            malformed_count: 0,
             requests_by_status_code: HashMap::new(),
             requests_by_section: HashMap::new(),
+            queries_by_section: HashMap::new(),
+            requests_by_method: HashMap::new(),
+            requests_by_protocol: HashMap::new(),
+            requests_by_user_agent: HashMap::new(),
+            requests_by_referer: HashMap::new(),
+            requests_by_source: HashMap::new(),
         }
     }
 
-    fn push(&mut self, record: &std::rc::Rc<RequestRecord>) -> anyhow::Result<Vec<String>> {
+    fn push(&mut self, record: &std::rc::Rc<RequestRecord>) -> Result<Vec<MonitorEvent>, Error> {
+        let section = self.sections.section(record);
+        self.push_in_section(record, section.as_deref())
+    }
+
+    fn push_in_section(
+        &mut self,
+        record: &std::rc::Rc<RequestRecord>,
+        section: Option<&str>,
+    ) -> Result<Vec<MonitorEvent>, Error> {
         let output = self.maybe_flush_before(record)?;
 
         self.requests.push(record.clone());
@@ -74,51 +132,84 @@ impl Monitor for ChunkedStatsMonitor {
             .entry(record.status)
             .and_modify(|n| *n += 1)
             .or_insert(1);
-        self.requests_by_section
-            .entry(String::from("/") + record.section())
-            .and_modify(|n| *n += 1)
-            .or_insert(1);
+        match section {
+            Some(section) => {
+                if record.request.query().is_some() {
+                    self.queries_by_section
+                        .entry(section.to_string())
+                        .and_modify(|n| *n += 1)
+                        .or_insert(1);
+                }
+                self.requests_by_section
+                    .entry(section.to_string())
+                    .and_modify(|n| *n += 1)
+                    .or_insert(1);
+            }
+            None => self.malformed_count += 1,
+        }
+        if let Some(method) = record.request.method() {
+            self.requests_by_method
+                .entry(method.clone())
+                .and_modify(|n| *n += 1)
+                .or_insert(1);
+        }
+        if let Some(protocol) = record.request.protocol() {
+            self.requests_by_protocol
+                .entry(protocol.clone())
+                .and_modify(|n| *n += 1)
+                .or_insert(1);
+        }
+        if let Some(user_agent) = &record.user_agent {
+            self.requests_by_user_agent
+                .entry(user_agent.clone())
+                .and_modify(|n| *n += 1)
+                .or_insert(1);
+        }
+        if let Some(referer) = &record.referer {
+            self.requests_by_referer
+                .entry(referer.clone())
+                .and_modify(|n| *n += 1)
+                .or_insert(1);
+        }
+        if let Some(source) = &record.source {
+            self.requests_by_source
+                .entry(source.clone())
+                .and_modify(|n| *n += 1)
+                .or_insert(1);
+        }
 
         Ok(output)
     }
 
-    fn pending(&mut self) -> anyhow::Result<Vec<String>> {
-        let range = self.requests_time_range.as_ref().unwrap();
-
-        let start = NaiveDateTime::from_timestamp(range.start.try_into().unwrap(), 0);
-        let end = NaiveDateTime::from_timestamp(range.end.try_into().unwrap(), 0).time();
-
-        let rate = self.request_count as f64 / self.chunk_seconds as f64;
-
-        let top_status_codes = self
-            .requests_by_status_code
-            .iter()
-            .map(|(code, count)| (count, code))
-            .sorted()
-            .rev()
-            .take(3)
-            .map(|(count, code)| format!("{:3}% {:03}", 100 * count / self.request_count, code))
-            .join(", ");
+    fn pending(&mut self) -> Result<Vec<MonitorEvent>, Error> {
+        // If we haven't seen any records, we don't have a chunk to report on.
+        let range = match self.requests_time_range.as_ref() {
+            Some(range) => range,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(vec![MonitorEvent::Stats(StatsWindow {
+            start: range.start,
+            end: range.end,
+            request_count: self.request_count,
+            request_rate: self.request_count as f64 / self.chunk_seconds as f64,
+            malformed_count: self.malformed_count,
+            sections: Self::by_frequency(&self.requests_by_section),
+            query_sections: Self::by_frequency(&self.queries_by_section),
+            status_codes: Self::by_frequency(&self.requests_by_status_code),
+            methods: Self::by_frequency(&self.requests_by_method),
+            protocols: Self::by_frequency(&self.requests_by_protocol),
+            user_agents: Self::by_frequency(&self.requests_by_user_agent),
+            referers: Self::by_frequency(&self.requests_by_referer),
+            sources: Self::by_frequency(&self.requests_by_source),
+        })])
+    }
 
-        let top_sections = self
-            .requests_by_section
-            .iter()
-            .map(|(section, count)| (count, section))
-            .sorted()
-            .rev()
-            .take(1)
-            .map(|(count, section)| {
-                format!("{:3}% in {:<11}", 100 * count / self.request_count, section)
-            })
-            .join(", ");
-
-        if self.request_count > 0 {
-            Ok(vec![format!(
-                "{}-{}  |  {:4} requests at {:5.1}rps  |  {}  |  {}",
-                start, end, self.request_count, rate, top_sections, top_status_codes
-            )])
-        } else {
-            Ok(vec![format!("{}-{}  |  no requests", start, end,)])
+    fn tick(&mut self, now: u32) -> Result<Vec<MonitorEvent>, Error> {
+        // We don't start our first chunk until we've seen a record.
+        match self.requests_time_range.clone() {
+            Some(requests_time_range) => self.flush_chunks_before(requests_time_range, now),
+            None => Ok(Vec::new()),
         }
     }
 }
@@ -1,12 +1,14 @@
 mod chunked_stats_monitor;
 mod rolling_alerts_monitor;
+mod user_alerts_monitor;
 
 pub use self::chunked_stats_monitor::ChunkedStatsMonitor;
 pub use self::rolling_alerts_monitor::RollingAlertsMonitor;
+pub use self::user_alerts_monitor::UserAlertsMonitor;
 
-use crate::Config;
+use crate::{Config, Error, Metrics, MonitorEvent};
 
-/// A monitor which follows a stream of requests and may produce output in response to events.
+/// A monitor which follows a stream of requests and may produce output events in response.
 pub trait Monitor: std::fmt::Debug {
     /// Creates a new instance of this monitor for a given program configuration.
     fn from_config(config: &Config) -> Self
@@ -16,20 +18,53 @@ pub trait Monitor: std::fmt::Debug {
         Self: Sized;
 
     /// Pushes a new request record into the monitor, returning any new output this produces.
+    ///
+    /// Monitors that can fail should return [Error::Monitor].
     fn push(
         &mut self,
         record: &std::rc::Rc<crate::models::RequestRecord>,
-    ) -> anyhow::Result<Vec<String>>;
+    ) -> Result<Vec<MonitorEvent>, Error>;
+
+    /// Pushes a record like [Monitor::push], along with its section as found by the config's
+    /// sections, or None if its request line is malformed. This saves monitors that group
+    /// requests by section from finding it again when the caller already has.
+    fn push_in_section(
+        &mut self,
+        record: &std::rc::Rc<crate::models::RequestRecord>,
+        section: Option<&str>,
+    ) -> Result<Vec<MonitorEvent>, Error> {
+        // Monitors that don't group by section can ignore it.
+        let _ = section;
+        self.push(record)
+    }
 
     /// Output for the records that haven't been accounted-for yet.
     ///
     /// Called to ensure that records at the end of a stream aren't missed if they fall in
     /// a chunk that hasn't yet been terminated by a subsequent push. May also be used to
     /// provide a ~live view of the current incomplete chunk.
-    fn pending(&mut self) -> anyhow::Result<Vec<String>> {
+    fn pending(&mut self) -> Result<Vec<MonitorEvent>, Error> {
         // If this doesn't apply for a given monitor, they don't need to implement it.
         // For example, RollingAlertsMonitor's output immediately reflects all of the events
         // it's been given so it never has any pending.
         Ok(Vec::new())
     }
+
+    /// Informs the monitor that every record before now has been pushed, returning any new
+    /// output this produces.
+    ///
+    /// Called periodically when streaming live input, so that chunks are closed and alerts
+    /// are re-evaluated while no records are arriving. `now` is a Unix timestamp in the same
+    /// clock as the records' dates.
+    fn tick(&mut self, now: u32) -> Result<Vec<MonitorEvent>, Error> {
+        // Monitors whose output only changes in response to records don't need this.
+        let _ = now;
+        Ok(Vec::new())
+    }
+
+    /// Records this monitor's current state in metrics, to be exposed to Prometheus.
+    fn update_metrics(&self, metrics: &mut Metrics) {
+        // Monitors don't need to expose any metrics.
+        let _ = metrics;
+    }
 }
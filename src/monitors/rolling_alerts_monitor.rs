@@ -1,9 +1,8 @@
-use std::{collections::VecDeque, convert::TryInto, fmt::Debug, rc::Rc};
+use std::{collections::VecDeque, fmt::Debug, rc::Rc};
 
-use chrono::NaiveDateTime;
-
-use crate::{Config, Monitor, RequestRecord};
+use crate::{Alert, Config, Error, Metrics, Monitor, MonitorEvent, RequestRecord};
 
+/// Monitor that alerts when the average request rate over a rolling window exceeds a threshold.
 #[derive(Debug, Default, Clone)]
 pub struct RollingAlertsMonitor {
     /// The number of seconds of requests to include in our rolling window.
@@ -19,23 +18,18 @@ pub struct RollingAlertsMonitor {
     requests: VecDeque<Rc<RequestRecord>>,
 }
 
-impl Monitor for RollingAlertsMonitor {
-    fn from_config(config: &Config) -> Self {
-        Self {
-            window_seconds: config.alert_window,
-            alert_rate: config.alert_rate,
-            ..Self::default()
-        }
-    }
-
-    fn push(&mut self, record: &std::rc::Rc<RequestRecord>) -> anyhow::Result<Vec<String>> {
+impl RollingAlertsMonitor {
+    /// Drops requests that are no longer in the window ending at now, then outputs an alert or
+    /// recovery event if that changes whether the alert is triggered.
+    fn evaluate(&mut self, now: u32) -> Vec<MonitorEvent> {
         let mut output = Vec::new();
 
-        self.requests.push_back(record.clone());
-
-        let min_time_exclusive = record.date - self.window_seconds;
+        let min_time_exclusive = now.saturating_sub(self.window_seconds);
 
-        while self.requests.front().unwrap().date <= min_time_exclusive {
+        while let Some(front) = self.requests.front() {
+            if front.date > min_time_exclusive {
+                break;
+            }
             self.requests.pop_front();
         }
 
@@ -43,17 +37,51 @@ impl Monitor for RollingAlertsMonitor {
 
         let alert_triggered = average >= self.alert_rate as f64;
 
-        let date = NaiveDateTime::from_timestamp(record.date.try_into().unwrap(), 0);
-
         if alert_triggered != self.alert_triggered {
             self.alert_triggered = alert_triggered;
-            if self.alert_triggered {
-                output.push(format!("{} ALERT-----+------> average of {:5.1}rps over last {:3} seconds exceeds threshold of  {:5.1}rps <-------ALERT", date, average, self.window_seconds, self.alert_rate as f64));
+
+            let alert = Alert {
+                date: now,
+                request_count: self.requests.len() as u64,
+                average_rate: average,
+                window_seconds: self.window_seconds,
+                threshold_rate: self.alert_rate,
+            };
+            output.push(if self.alert_triggered {
+                MonitorEvent::AlertTriggered(alert)
             } else {
-                output.push(format!("{} RECOVERY--+------> average of {:5.1}rps over last {:3} seconds is below threshold of {:5.1}rps <----RECOVERY", date, average, self.window_seconds, self.alert_rate as f64));
-            }
+                MonitorEvent::AlertRecovered(alert)
+            });
+        }
+
+        output
+    }
+}
+
+impl Monitor for RollingAlertsMonitor {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            window_seconds: config.alert_window,
+            alert_rate: config.alert_rate,
+            ..Self::default()
         }
+    }
+
+    fn push(&mut self, record: &std::rc::Rc<RequestRecord>) -> Result<Vec<MonitorEvent>, Error> {
+        self.requests.push_back(record.clone());
+
+        Ok(self.evaluate(record.date))
+    }
+
+    fn tick(&mut self, now: u32) -> Result<Vec<MonitorEvent>, Error> {
+        // Requests leaving the window can clear an alert, which is how we recover once traffic
+        // stops entirely.
+        Ok(self.evaluate(now))
+    }
 
-        Ok(output)
+    fn update_metrics(&self, metrics: &mut Metrics) {
+        metrics.alert_average_rate = self.requests.len() as f64 / self.window_seconds as f64;
+        metrics.alert_threshold_rate = self.alert_rate as f64;
+        metrics.alert_firing = self.alert_triggered;
     }
 }
@@ -0,0 +1,145 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::Debug,
+    rc::Rc,
+};
+
+use string_cache::DefaultAtom;
+
+use crate::{Alert, Config, Error, Metrics, Monitor, MonitorEvent, RequestRecord, UserAlert};
+
+/// Monitor that alerts when the average request rate from a single authenticated user over a
+/// rolling window exceeds a threshold, such as when one account is hammering the API.
+///
+/// Requests without an authenticated user aren't counted.
+#[derive(Debug, Default, Clone)]
+pub struct UserAlertsMonitor {
+    /// The number of seconds of requests to include in our rolling window.
+    window_seconds: u32,
+
+    /// The average number of requests per second from one user through the window required to
+    /// trigger an alert for them, or None if users aren't alerted on.
+    alert_rate: Option<u32>,
+
+    /// The users whose alert is currently triggered.
+    triggered: BTreeSet<DefaultAtom>,
+
+    /// The date and user of each request in the current alerting window.
+    requests: VecDeque<(u32, DefaultAtom)>,
+
+    /// The number of requests in the current alerting window from each user.
+    counts: HashMap<DefaultAtom, u64>,
+}
+
+impl UserAlertsMonitor {
+    /// Drops requests that are no longer in the window ending at now, then outputs a recovery
+    /// event for each triggered user who has fallen below the threshold, and an alert event
+    /// if pushed_user has reached it.
+    fn evaluate(
+        &mut self,
+        now: u32,
+        alert_rate: u32,
+        pushed_user: Option<&DefaultAtom>,
+    ) -> Vec<MonitorEvent> {
+        let mut output = Vec::new();
+
+        let min_time_exclusive = now.saturating_sub(self.window_seconds);
+
+        while let Some((date, _)) = self.requests.front() {
+            if *date > min_time_exclusive {
+                break;
+            }
+            let (_, user) = self.requests.pop_front().expect("front should exist");
+            let count = self.counts.get_mut(&user).expect("users should be counted");
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&user);
+            }
+        }
+
+        let recovered = self
+            .triggered
+            .iter()
+            .filter(|user| !self.is_triggered(user, alert_rate))
+            .cloned()
+            .collect::<Vec<_>>();
+        for user in recovered {
+            self.triggered.remove(&user);
+            output.push(MonitorEvent::UserAlertRecovered(
+                self.alert(now, alert_rate, user),
+            ));
+        }
+
+        if let Some(user) = pushed_user {
+            if !self.triggered.contains(user) && self.is_triggered(user, alert_rate) {
+                self.triggered.insert(user.clone());
+                output.push(MonitorEvent::UserAlertTriggered(self.alert(
+                    now,
+                    alert_rate,
+                    user.clone(),
+                )));
+            }
+        }
+
+        output
+    }
+
+    /// The number of requests in the window from user.
+    fn count(&self, user: &DefaultAtom) -> u64 {
+        self.counts.get(user).copied().unwrap_or(0)
+    }
+
+    /// Whether the average rate of requests from user through the window reaches alert_rate.
+    fn is_triggered(&self, user: &DefaultAtom, alert_rate: u32) -> bool {
+        self.count(user) as f64 / self.window_seconds as f64 >= alert_rate as f64
+    }
+
+    /// The current state of user's alert.
+    fn alert(&self, now: u32, alert_rate: u32, user: DefaultAtom) -> UserAlert {
+        let request_count = self.count(&user);
+        UserAlert {
+            user,
+            alert: Alert {
+                date: now,
+                request_count,
+                average_rate: request_count as f64 / self.window_seconds as f64,
+                window_seconds: self.window_seconds,
+                threshold_rate: alert_rate,
+            },
+        }
+    }
+}
+
+impl Monitor for UserAlertsMonitor {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            window_seconds: config.alert_window,
+            alert_rate: config.user_alert_rate,
+            ..Self::default()
+        }
+    }
+
+    fn push(&mut self, record: &Rc<RequestRecord>) -> Result<Vec<MonitorEvent>, Error> {
+        let (alert_rate, user) = match (self.alert_rate, &record.auth_user) {
+            (Some(alert_rate), Some(user)) => (alert_rate, user),
+            (Some(alert_rate), None) => return Ok(self.evaluate(record.date, alert_rate, None)),
+            (None, _) => return Ok(Vec::new()),
+        };
+
+        self.requests.push_back((record.date, user.clone()));
+        *self.counts.entry(user.clone()).or_insert(0) += 1;
+
+        Ok(self.evaluate(record.date, alert_rate, Some(user)))
+    }
+
+    fn tick(&mut self, now: u32) -> Result<Vec<MonitorEvent>, Error> {
+        Ok(match self.alert_rate {
+            Some(alert_rate) => self.evaluate(now, alert_rate, None),
+            None => Vec::new(),
+        })
+    }
+
+    fn update_metrics(&self, metrics: &mut Metrics) {
+        metrics.user_alerts_firing = self.triggered.len() as u64;
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use serde::{Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use string_cache::DefaultAtom;
+
+use super::{Delivery, DeliveryQueue, Notifier, Request};
+use crate::{models::serialize_timestamp, render_text, Alert, MonitorEvent, UserAlert};
+
+/// The alertname label of the request rate alert.
+const ALERT_NAME: &str = "HighRequestRate";
+
+/// The alertname label of the request rate alert for a single user, which also has a user
+/// label.
+const USER_ALERT_NAME: &str = "HighUserRequestRate";
+
+/// The section label of the request rate alert, which covers requests to every section.
+const ALL_SECTIONS: &str = "all";
+
+/// Configuration for posting alerts to Prometheus Alertmanager.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertmanagerConfig {
+    /// The base URL of Alertmanager, such as `http://127.0.0.1:9093`.
+    pub url: String,
+    /// The severity label of the alert.
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    /// Additional labels for the alert, such as the instance being monitored.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// The number of seconds between re-posting alerts that are still firing, which must be
+    /// less than Alertmanager's `resolve_timeout` for them to stay firing.
+    #[serde(default = "default_repost_seconds")]
+    pub repost_seconds: u64,
+    /// How requests are timed out, retried and queued.
+    #[serde(default)]
+    pub delivery: Delivery,
+}
+
+fn default_severity() -> String {
+    "warning".to_string()
+}
+
+fn default_repost_seconds() -> u64 {
+    60
+}
+
+impl AlertmanagerConfig {
+    /// Configures posting to the Alertmanager at url, with the default labels and delivery.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            severity: default_severity(),
+            labels: BTreeMap::new(),
+            repost_seconds: default_repost_seconds(),
+            delivery: Delivery::default(),
+        }
+    }
+}
+
+/// Posts the request rate alert and each user's request rate alert to Alertmanager's
+/// `/api/v2/alerts` endpoint, as firing when they're triggered and as resolved when they
+/// recover.
+///
+/// Alertmanager resolves alerts that haven't been posted for its `resolve_timeout`, so alerts
+/// that are still firing are re-posted every [AlertmanagerConfig::repost_seconds] as the
+/// notifier is ticked.
+#[derive(Debug)]
+pub struct AlertmanagerNotifier {
+    config: AlertmanagerConfig,
+    /// The URL of the alerts endpoint.
+    alerts_url: String,
+    /// When the alert was triggered, if it's firing.
+    started: Option<u32>,
+    /// When each user's alert was triggered, for the users whose alert is firing.
+    users_started: HashMap<DefaultAtom, u32>,
+    /// The body last posted for each firing alert, keyed by user for user alerts, to re-post.
+    firing: HashMap<Option<DefaultAtom>, String>,
+    /// When the firing alerts were last posted.
+    last_posted: Instant,
+    queue: DeliveryQueue,
+}
+
+/// An alert in the format of Alertmanager's API.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostableAlert<'a> {
+    labels: BTreeMap<&'a str, &'a str>,
+    annotations: BTreeMap<&'a str, String>,
+    starts_at: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ends_at: Option<Timestamp>,
+}
+
+/// A Unix timestamp, serialized in RFC 3339 format.
+#[derive(Debug)]
+struct Timestamp(u32);
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_timestamp(&self.0, serializer)
+    }
+}
+
+impl AlertmanagerNotifier {
+    /// Starts a worker to deliver alerts to the configured Alertmanager.
+    pub fn new(config: &AlertmanagerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            alerts_url: format!("{}/api/v2/alerts", config.url.trim_end_matches('/')),
+            started: None,
+            users_started: HashMap::new(),
+            firing: HashMap::new(),
+            last_posted: Instant::now(),
+            queue: DeliveryQueue::new(format!("alertmanager {}", config.url), &config.delivery),
+        }
+    }
+
+    /// Formats the alerts to post for a change in alert state, with the alert's own labels
+    /// added to the configured ones.
+    fn body(
+        &self,
+        event: &MonitorEvent,
+        alert_labels: &[(&str, &str)],
+        alert: &Alert,
+        started: Option<u32>,
+        ends_at: Option<u32>,
+    ) -> String {
+        let mut labels: BTreeMap<&str, &str> = self
+            .config
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        labels.extend(alert_labels.iter().copied());
+        labels.insert("section", ALL_SECTIONS);
+        labels.insert("severity", &self.config.severity);
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert("summary", render_text(event).join("\n"));
+        annotations.insert(
+            "description",
+            format!(
+                "average of {:.1} requests per second over the last {} seconds, threshold is {}",
+                alert.average_rate, alert.window_seconds, alert.threshold_rate
+            ),
+        );
+
+        let alerts = [PostableAlert {
+            labels,
+            annotations,
+            starts_at: Timestamp(started.unwrap_or(alert.date)),
+            ends_at: ends_at.map(Timestamp),
+        }];
+        serde_json::to_string(&alerts).expect("alerts should always be serializable")
+    }
+
+    /// Queues body to be posted.
+    fn post(&self, body: String) {
+        self.queue.send(Request {
+            url: self.alerts_url.clone(),
+            content_type: "application/json".to_string(),
+            body,
+        });
+    }
+}
+
+impl Notifier for AlertmanagerNotifier {
+    fn notify(&mut self, event: &MonitorEvent) {
+        let labels = [("alertname", ALERT_NAME)];
+        let body = match event {
+            MonitorEvent::AlertTriggered(alert) => {
+                self.started = Some(alert.date);
+                let body = self.body(event, &labels, alert, self.started, None);
+                self.firing.insert(None, body.clone());
+                body
+            }
+            MonitorEvent::AlertRecovered(alert) => {
+                let started = self.started.take();
+                self.firing.remove(&None);
+                self.body(event, &labels, alert, started, Some(alert.date))
+            }
+            MonitorEvent::UserAlertTriggered(UserAlert { user, alert }) => {
+                self.users_started.insert(user.clone(), alert.date);
+                let labels = [("alertname", USER_ALERT_NAME), ("user", user)];
+                let body = self.body(event, &labels, alert, Some(alert.date), None);
+                self.firing.insert(Some(user.clone()), body.clone());
+                body
+            }
+            MonitorEvent::UserAlertRecovered(UserAlert { user, alert }) => {
+                let started = self.users_started.remove(user);
+                self.firing.remove(&Some(user.clone()));
+                let labels = [("alertname", USER_ALERT_NAME), ("user", user)];
+                self.body(event, &labels, alert, started, Some(alert.date))
+            }
+            MonitorEvent::Stats(_) => return,
+        };
+
+        self.post(body);
+    }
+
+    fn tick(&mut self) {
+        let interval = Duration::from_secs(self.config.repost_seconds);
+        if self.last_posted.elapsed() < interval {
+            return;
+        }
+        self.last_posted = Instant::now();
+        for body in self.firing.values() {
+            self.post(body.clone());
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        self.queue.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::tests::http_stand_in;
+
+    fn alert(date: u32, average_rate: f64) -> Alert {
+        Alert {
+            date,
+            request_count: (average_rate * 120.0) as u64,
+            average_rate,
+            window_seconds: 120,
+            threshold_rate: 10,
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let (url, bodies) = http_stand_in(vec![200, 200, 200]);
+        let mut config = AlertmanagerConfig::new(&url);
+        config
+            .labels
+            .insert("instance".to_string(), "web-1".to_string());
+
+        let mut notifier: Box<dyn Notifier> = Box::new(AlertmanagerNotifier::new(&config));
+        notifier.notify(&MonitorEvent::AlertTriggered(alert(1549573860, 12.5)));
+        notifier.notify(&MonitorEvent::AlertRecovered(alert(1549574000, 9.5)));
+        notifier.notify(&MonitorEvent::UserAlertRecovered(UserAlert {
+            user: "alice".into(),
+            alert: alert(1549574000, 4.5),
+        }));
+        notifier.finish();
+
+        let bodies: Vec<serde_json::Value> = bodies
+            .iter()
+            .map(|body| serde_json::from_str(&body).unwrap())
+            .collect();
+
+        let firing = &bodies[0][0];
+        assert_eq!(firing["labels"]["alertname"], "HighRequestRate");
+        assert_eq!(firing["labels"]["section"], "all");
+        assert_eq!(firing["labels"]["severity"], "warning");
+        assert_eq!(firing["labels"]["instance"], "web-1");
+        assert_eq!(firing["startsAt"], "2019-02-07T21:11:00Z");
+        assert!(firing.get("endsAt").is_none());
+
+        let resolved = &bodies[1][0];
+        assert_eq!(resolved["labels"], firing["labels"]);
+        assert_eq!(resolved["startsAt"], "2019-02-07T21:11:00Z");
+        assert_eq!(resolved["endsAt"], "2019-02-07T21:13:20Z");
+
+        let user = &bodies[2][0];
+        assert_eq!(user["labels"]["alertname"], "HighUserRequestRate");
+        assert_eq!(user["labels"]["user"], "alice");
+        assert_eq!(user["endsAt"], "2019-02-07T21:13:20Z");
+    }
+
+    #[test]
+    fn test_repost() {
+        let (url, bodies) = http_stand_in(vec![200, 200, 200]);
+        let config = AlertmanagerConfig {
+            repost_seconds: 0,
+            ..AlertmanagerConfig::new(&url)
+        };
+
+        let mut notifier: Box<dyn Notifier> = Box::new(AlertmanagerNotifier::new(&config));
+        notifier.notify(&MonitorEvent::AlertTriggered(alert(1549573860, 12.5)));
+        notifier.tick();
+        notifier.notify(&MonitorEvent::AlertRecovered(alert(1549574000, 9.5)));
+        // Nothing is firing any more, so there's nothing to re-post.
+        notifier.tick();
+        notifier.finish();
+
+        let bodies: Vec<String> = bodies.iter().collect();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[1], bodies[0]);
+        assert_ne!(bodies[2], bodies[0]);
+    }
+}
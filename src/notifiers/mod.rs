@@ -0,0 +1,415 @@
+//! Notifiers that send alert state changes to external services.
+
+mod alertmanager;
+mod webhook;
+
+use std::{
+    fmt::Debug,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+pub use self::alertmanager::{AlertmanagerConfig, AlertmanagerNotifier};
+pub(crate) use self::webhook::check_template;
+pub use self::webhook::{WebhookConfig, WebhookNotifier};
+use crate::{ConfigError, MonitorEvent};
+
+/// Sends alert state changes to an external service.
+pub trait Notifier: Debug {
+    /// Queues a notification for an alert being triggered or recovered. Must not block.
+    fn notify(&mut self, event: &MonitorEvent);
+
+    /// Called regularly while monitoring, so that notifications of alerts that are still
+    /// firing can be repeated. Must not block.
+    fn tick(&mut self) {}
+
+    /// Waits for queued notifications to be delivered, until they run out of retries or the
+    /// shutdown timeout passes.
+    fn finish(self: Box<Self>);
+}
+
+/// The notifiers to send alert state changes to.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifiers {
+    /// Webhooks to POST a payload to for each alert state change.
+    pub webhooks: Vec<WebhookConfig>,
+    /// Prometheus Alertmanagers to post the alert to as it fires and resolves.
+    pub alertmanagers: Vec<AlertmanagerConfig>,
+}
+
+impl Notifiers {
+    /// Creates each of the configured notifiers.
+    pub fn create(&self) -> Result<Vec<Box<dyn Notifier>>, ConfigError> {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        for config in &self.webhooks {
+            notifiers.push(Box::new(WebhookNotifier::new(config)?));
+        }
+        for config in &self.alertmanagers {
+            notifiers.push(Box::new(AlertmanagerNotifier::new(config)));
+        }
+        Ok(notifiers)
+    }
+}
+
+/// How to deliver HTTP requests to a notification endpoint.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Delivery {
+    /// The number of seconds to wait for each request to complete.
+    pub timeout_seconds: u64,
+    /// The number of times to retry a request that fails, or gets a 5xx or 429 response.
+    pub max_retries: u32,
+    /// The number of milliseconds to wait before the first retry, doubling for each after.
+    pub initial_backoff_millis: u64,
+    /// The longest we'll wait between retries, in milliseconds.
+    pub max_backoff_millis: u64,
+    /// The number of notifications that may be waiting to be delivered. Further
+    /// notifications are dropped, so that a slow endpoint doesn't stall log processing.
+    pub queue_capacity: usize,
+    /// The number of seconds to keep delivering queued notifications for when the monitors
+    /// finish. Requests still waiting or being retried after that are dropped.
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl Default for Delivery {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 10,
+            max_retries: 5,
+            initial_backoff_millis: 500,
+            max_backoff_millis: 60_000,
+            queue_capacity: 100,
+            shutdown_timeout_seconds: 30,
+        }
+    }
+}
+
+impl Delivery {
+    /// The time to wait before the given retry, counting from zero.
+    fn backoff(&self, retry: u32) -> Duration {
+        let millis = self
+            .initial_backoff_millis
+            .saturating_mul(1 << retry.min(32))
+            .min(self.max_backoff_millis);
+        Duration::from_millis(millis)
+    }
+}
+
+/// An HTTP request to be delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Request {
+    url: String,
+    content_type: String,
+    body: String,
+}
+
+/// The time by which a worker should give up on its remaining requests, once it's set.
+/// Setting it wakes a worker waiting to retry, so that it can give up straight away.
+#[derive(Debug, Default)]
+struct Deadline {
+    time: Mutex<Option<Instant>>,
+    set: Condvar,
+}
+
+impl Deadline {
+    fn set(&self, time: Instant) {
+        *self.time.lock().unwrap() = Some(time);
+        self.set.notify_all();
+    }
+
+    /// The time left before the deadline, or None if it hasn't been set.
+    fn remaining(&self) -> Option<Duration> {
+        self.time
+            .lock()
+            .unwrap()
+            .map(|time| time.saturating_duration_since(Instant::now()))
+    }
+
+    /// Waits for duration, returning true, unless the deadline is or gets set to before the
+    /// end of it, in which case it returns false as soon as it's set.
+    fn wait(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        let mut time = self.time.lock().unwrap();
+        loop {
+            if time.is_some_and(|time| time < end) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            time = self.set.wait_timeout(time, end - now).unwrap().0;
+        }
+    }
+}
+
+/// Delivers HTTP POST requests from a bounded queue on a background thread, with retries.
+#[derive(Debug)]
+struct DeliveryQueue {
+    /// A name for the endpoint, for log messages.
+    name: String,
+    sender: SyncSender<Request>,
+    worker: JoinHandle<()>,
+    deadline: Arc<Deadline>,
+    shutdown_timeout: Duration,
+}
+
+impl DeliveryQueue {
+    fn new(name: String, delivery: &Delivery) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Request>(delivery.queue_capacity);
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(delivery.timeout_seconds))
+            .build();
+        let shutdown_timeout = Duration::from_secs(delivery.shutdown_timeout_seconds);
+        let delivery = delivery.clone();
+        let worker_name = name.clone();
+        let deadline = Arc::new(Deadline::default());
+        let worker_deadline = Arc::clone(&deadline);
+
+        let worker = thread::spawn(move || {
+            let mut dropped = 0;
+            for request in receiver {
+                // Once past the deadline, the rest are dropped without being tried.
+                if worker_deadline.remaining() == Some(Duration::ZERO)
+                    || !deliver(&agent, &delivery, &worker_name, &request, &worker_deadline)
+                {
+                    dropped += 1;
+                }
+            }
+            if dropped > 0 {
+                log::error!(
+                    "dropped {} notifications for {} that weren't delivered before shutdown",
+                    dropped,
+                    worker_name
+                );
+            }
+        });
+
+        Self {
+            name,
+            sender,
+            worker,
+            deadline,
+            shutdown_timeout,
+        }
+    }
+
+    /// Queues a request, or drops it if the queue is full.
+    fn send(&self, request: Request) {
+        match self.sender.try_send(request) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => log::warn!(
+                "dropping notification for {}, too many are waiting to be delivered",
+                self.name
+            ),
+            Err(TrySendError::Disconnected(_)) => {
+                log::error!(
+                    "dropping notification for {}, its worker has stopped",
+                    self.name
+                )
+            }
+        }
+    }
+
+    /// Waits for the queued requests to be delivered, until they run out of retries or the
+    /// shutdown timeout passes. A request in progress at the deadline may still take up to the
+    /// request timeout to complete.
+    fn finish(self) {
+        self.deadline.set(Instant::now() + self.shutdown_timeout);
+        drop(self.sender);
+        if self.worker.join().is_err() {
+            log::error!("notification worker for {} panicked", self.name);
+        }
+    }
+}
+
+/// Sends request, retrying with exponential backoff if it fails in a way that may be temporary,
+/// until the deadline. Returns false if it was still failing at the deadline, in which case the
+/// error is left to the caller to report.
+fn deliver(
+    agent: &ureq::Agent,
+    delivery: &Delivery,
+    name: &str,
+    request: &Request,
+    deadline: &Deadline,
+) -> bool {
+    let mut retry = 0;
+    loop {
+        let result = agent
+            .post(&request.url)
+            .set("Content-Type", &request.content_type)
+            .send_string(&request.body);
+
+        let error = match result {
+            Ok(_) => {
+                log::debug!("delivered notification to {}", name);
+                return true;
+            }
+            Err(ureq::Error::Status(status, _)) if status != 429 && status < 500 => {
+                log::error!(
+                    "notification to {} was rejected with status {}, not retrying",
+                    name,
+                    status
+                );
+                return true;
+            }
+            Err(error) => error,
+        };
+
+        if retry >= delivery.max_retries {
+            log::error!(
+                "failed to deliver notification to {} after {} attempts: {}",
+                name,
+                retry + 1,
+                error
+            );
+            return true;
+        }
+
+        let backoff = delivery.backoff(retry);
+        log::warn!(
+            "failed to deliver notification to {}, retrying in {:?}: {}",
+            name,
+            backoff,
+            error
+        );
+        if !deadline.wait(backoff) {
+            log::debug!("giving up on notification to {}: {}", name, error);
+            return false;
+        }
+        retry += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{Ipv4Addr, TcpListener},
+        sync::mpsc::Receiver,
+    };
+
+    use super::*;
+
+    /// A local HTTP server that responds to each request with the next of statuses, and sends
+    /// the bodies of the requests it receives to the returned channel.
+    pub(super) fn http_stand_in(statuses: Vec<u16>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (status, stream) in statuses.into_iter().zip(listener.incoming()) {
+                let mut stream = BufReader::new(stream.unwrap());
+
+                let mut content_length = 0;
+                let mut line = String::new();
+                while stream.read_line(&mut line).unwrap() > 0 && !line.trim().is_empty() {
+                    let lowercase = line.to_ascii_lowercase();
+                    if let Some(length) = lowercase.strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; content_length];
+                stream.read_exact(&mut body).unwrap();
+
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                sender.send(String::from_utf8(body).unwrap()).unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn request(url: &str, body: &str) -> Request {
+        Request {
+            url: url.to_string(),
+            content_type: "text/plain".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let delivery = Delivery::default();
+        assert_eq!(delivery.backoff(0), Duration::from_millis(500));
+        assert_eq!(delivery.backoff(3), Duration::from_millis(4000));
+        assert_eq!(delivery.backoff(40), Duration::from_millis(60_000));
+    }
+
+    #[test]
+    fn test_retries() {
+        let (url, bodies) = http_stand_in(vec![500, 503, 200, 400]);
+        let queue = DeliveryQueue::new(
+            "test".to_string(),
+            &Delivery {
+                initial_backoff_millis: 1,
+                ..Delivery::default()
+            },
+        );
+
+        queue.send(request(&url, "one"));
+        // A client error isn't retried.
+        queue.send(request(&url, "two"));
+        queue.finish();
+
+        let bodies: Vec<String> = bodies.iter().collect();
+        assert_eq!(bodies, vec!["one", "one", "one", "two"]);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let (url, bodies) = http_stand_in(vec![500, 500, 200]);
+        let queue = DeliveryQueue::new(
+            "test".to_string(),
+            &Delivery {
+                initial_backoff_millis: 1,
+                max_retries: 1,
+                ..Delivery::default()
+            },
+        );
+
+        queue.send(request(&url, "one"));
+        queue.send(request(&url, "two"));
+        queue.finish();
+
+        let bodies: Vec<String> = bodies.iter().collect();
+        assert_eq!(bodies, vec!["one", "one", "two"]);
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let (url, bodies) = http_stand_in(vec![500]);
+        let queue = DeliveryQueue::new(
+            "test".to_string(),
+            &Delivery {
+                initial_backoff_millis: 60_000,
+                shutdown_timeout_seconds: 1,
+                ..Delivery::default()
+            },
+        );
+
+        queue.send(request(&url, "one"));
+        // Once the first attempt has failed, the worker is waiting (or about to wait) a
+        // minute to retry.
+        assert_eq!(bodies.recv().unwrap(), "one");
+        let start = Instant::now();
+        queue.finish();
+        // It's given up on as soon as the deadline is set.
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(bodies.recv().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::{Delivery, DeliveryQueue, Notifier, Request};
+use crate::{render_json, render_text, ConfigError, InvalidConfigField, MonitorEvent};
+
+/// The placeholders that may be used in a webhook body template, besides the event's fields.
+const WHOLE_EVENT: &str = "event";
+const EVENT_TEXT: &str = "text";
+
+/// The fields of an alert event that may be used as placeholders in a body template. The user
+/// is null except in user alert events.
+const EVENT_FIELDS: [&str; 7] = [
+    "type",
+    "user",
+    "date",
+    "request_count",
+    "average_rate",
+    "window_seconds",
+    "threshold_rate",
+];
+
+/// Configuration for POSTing alert state changes to a webhook.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The URL to POST to, such as `https://hooks.example.com/alerts`.
+    pub url: String,
+    /// The body to send, with placeholders like `{{average_rate}}` replaced by the alert's
+    /// fields, as JSON-escaped text without quotes. `{{event}}` is replaced by the whole alert
+    /// as JSON, and `{{text}}` by its text output. Defaults to the alert as JSON, as written
+    /// with `--output json`.
+    #[serde(default)]
+    pub body_template: Option<String>,
+    /// The Content-Type of the body.
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// How requests are timed out, retried and queued.
+    #[serde(default)]
+    pub delivery: Delivery,
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+impl WebhookConfig {
+    /// Configures POSTing to url, with the default body and delivery.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            body_template: None,
+            content_type: default_content_type(),
+            delivery: Delivery::default(),
+        }
+    }
+}
+
+/// POSTs a payload to a webhook each time an alert is triggered or recovers.
+///
+/// Requests are made on a background thread, so a slow webhook doesn't stall log processing.
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    queue: DeliveryQueue,
+}
+
+impl WebhookNotifier {
+    /// Starts a worker to deliver notifications to the configured webhook, after checking that
+    /// the body template only uses known placeholders.
+    pub fn new(config: &WebhookConfig) -> Result<Self, ConfigError> {
+        if let Some(template) = &config.body_template {
+            check_template(template).map_err(|reason| ConfigError {
+                fields: vec![InvalidConfigField {
+                    key: "body_template".to_string(),
+                    reason,
+                }],
+            })?;
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            queue: DeliveryQueue::new(format!("webhook {}", config.url), &config.delivery),
+        })
+    }
+
+    /// Formats the body to send for event.
+    fn body(&self, event: &MonitorEvent) -> String {
+        let template = match &self.config.body_template {
+            Some(template) => template,
+            None => return render_json(event),
+        };
+
+        let fields = serde_json::to_value(event).expect("events should always be serializable");
+        let mut body = String::with_capacity(template.len());
+        let mut rest = template.as_str();
+
+        while let Some((before, name, after)) = next_placeholder(rest) {
+            body.push_str(before);
+            let value = match name {
+                WHOLE_EVENT => render_json(event),
+                EVENT_TEXT => escape(&render_text(event).join("\n")),
+                _ => match &fields[name] {
+                    serde_json::Value::String(value) => escape(value),
+                    value => value.to_string(),
+                },
+            };
+            body.push_str(&value);
+            rest = after;
+        }
+        body.push_str(rest);
+
+        body
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, event: &MonitorEvent) {
+        self.queue.send(Request {
+            url: self.config.url.clone(),
+            content_type: self.config.content_type.clone(),
+            body: self.body(event),
+        });
+    }
+
+    fn finish(self: Box<Self>) {
+        self.queue.finish()
+    }
+}
+
+/// Checks that template only uses known placeholders, describing the first that isn't.
+pub(crate) fn check_template(template: &str) -> Result<(), String> {
+    match placeholders(template)
+        .into_iter()
+        .find(|name| *name != WHOLE_EVENT && *name != EVENT_TEXT && !EVENT_FIELDS.contains(name))
+    {
+        Some(name) => Err(format!(
+            "has an unknown placeholder {{{{{}}}}}, expected one of: {}, {}, {}",
+            name,
+            WHOLE_EVENT,
+            EVENT_TEXT,
+            EVENT_FIELDS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Splits text around its first `{{name}}` placeholder, returning the text before it, the
+/// trimmed name, and the text after it.
+fn next_placeholder(text: &str) -> Option<(&str, &str, &str)> {
+    let start = text.find("{{")?;
+    let end = start + text[start..].find("}}")?;
+    Some((
+        &text[..start],
+        text[start + 2..end].trim(),
+        &text[end + 2..],
+    ))
+}
+
+/// The names of the placeholders in template.
+fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some((_, name, after)) = next_placeholder(rest) {
+        names.push(name);
+        rest = after;
+    }
+    names
+}
+
+/// Escapes text to be placed inside a JSON string.
+fn escape(text: &str) -> String {
+    let quoted = serde_json::Value::from(text).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{notifiers::tests::http_stand_in, Alert};
+
+    fn alert() -> MonitorEvent {
+        MonitorEvent::AlertTriggered(Alert {
+            date: 1549573860,
+            request_count: 1500,
+            average_rate: 12.5,
+            window_seconds: 120,
+            threshold_rate: 10,
+        })
+    }
+
+    #[test]
+    fn test_body_template() {
+        let notifier = WebhookNotifier::new(&WebhookConfig {
+            body_template: Some(
+                r#"{"text": "{{ text }}", "rate": {{average_rate}}, "at": "{{date}}", "kind": "{{type}}"}"#
+                    .to_string(),
+            ),
+            ..WebhookConfig::new("http://127.0.0.1:9/")
+        })
+        .unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&notifier.body(&alert())).unwrap();
+        assert_eq!(body["rate"], 12.5);
+        assert_eq!(body["at"], "2019-02-07T21:11:00Z");
+        assert_eq!(body["kind"], "alert_triggered");
+        assert!(body["text"].as_str().unwrap().contains("ALERT-----+"));
+
+        assert!(WebhookNotifier::new(&WebhookConfig {
+            body_template: Some("{{rate}}".to_string()),
+            ..WebhookConfig::new("http://127.0.0.1:9/")
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_notify() {
+        let (url, bodies) = http_stand_in(vec![503, 200]);
+        let mut config = WebhookConfig::new(&url);
+        config.delivery.initial_backoff_millis = 1;
+
+        let mut notifier: Box<dyn Notifier> = Box::new(WebhookNotifier::new(&config).unwrap());
+        notifier.notify(&alert());
+        notifier.finish();
+
+        let bodies: Vec<String> = bodies.iter().collect();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[1], render_json(&alert()));
+    }
+}
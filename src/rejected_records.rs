@@ -0,0 +1,217 @@
+use std::{cell::RefCell, fmt, fs::File, io::LineWriter, path::PathBuf, str::FromStr};
+
+use serde_derive::{Deserialize, Serialize};
+use string_cache::DefaultAtom;
+
+use crate::{error_chain, sorted_request_iterator::append_json, Error, RequestRecord};
+
+/// What to do with a record that can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorPolicy {
+    /// Log the record's position and error, and carry on.
+    Skip,
+    /// Stop reading with an error.
+    Fail,
+    /// Skip the record, appending its position and error as a line of JSON to the file at
+    /// this path.
+    DeadLetter(PathBuf),
+}
+
+impl fmt::Display for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorPolicy::Skip => f.write_str("skip"),
+            ErrorPolicy::Fail => f.write_str("fail"),
+            ErrorPolicy::DeadLetter(path) => write!(f, "dead-letter={}", path.display()),
+        }
+    }
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "fail" => Ok(ErrorPolicy::Fail),
+            _ => match value.strip_prefix("dead-letter=") {
+                Some(path) if !path.is_empty() => Ok(ErrorPolicy::DeadLetter(path.into())),
+                _ => Err(format!(
+                    "unknown error policy {:?}, expected one of: skip, fail, dead-letter=PATH",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/// A record that couldn't be parsed, as written to the dead letter file.
+#[derive(Debug, Serialize)]
+struct RejectedRecord<'a> {
+    /// The name of the input, if there are several.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<&'a str>,
+    line: u64,
+    offset: u64,
+    error: String,
+}
+
+/// Handles records that can't be parsed according to an [ErrorPolicy], counting those that are
+/// skipped.
+#[derive(Debug)]
+pub(crate) struct RejectedRecords {
+    policy: ErrorPolicy,
+    skipped: u64,
+    dead_letters: Option<LineWriter<File>>,
+}
+
+impl RejectedRecords {
+    pub(crate) fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            skipped: 0,
+            dead_letters: None,
+        }
+    }
+
+    /// Handles an error from reading the named input, or the only input if name is None.
+    /// Returns the error back if it should stop the run, because the policy is to fail or
+    /// because it isn't a single record that failed to parse.
+    pub(crate) fn handle(&mut self, input: Option<&str>, error: Error) -> Result<(), Error> {
+        let (position, source) = match (&self.policy, &error) {
+            (ErrorPolicy::Fail, _) => return Err(error),
+            (_, Error::Parse { position, source }) => (position, source),
+            _ => return Err(error),
+        };
+
+        let input_prefix = input.map_or(String::new(), |name| format!("{}: ", name));
+        match &self.policy {
+            ErrorPolicy::DeadLetter(path) => {
+                let record = RejectedRecord {
+                    input,
+                    line: position.line,
+                    offset: position.offset,
+                    error: source.to_string(),
+                };
+                append_json(&mut self.dead_letters, path, &record).map_err(|source| {
+                    Error::DeadLetter {
+                        path: path.clone(),
+                        source,
+                    }
+                })?;
+                log::debug!("skipping record: {}{}", input_prefix, error_chain(&error));
+            }
+            _ => log::warn!("skipping record: {}{}", input_prefix, error_chain(&error)),
+        }
+
+        self.skipped += 1;
+        Ok(())
+    }
+
+    /// Logs a summary of the records that were skipped, if any.
+    pub(crate) fn report(&self) {
+        if self.skipped == 0 {
+            return;
+        }
+
+        match &self.policy {
+            ErrorPolicy::DeadLetter(path) => log::warn!(
+                "skipped {} records that couldn't be parsed, and wrote them to {}",
+                self.skipped,
+                path.display()
+            ),
+            _ => log::warn!("skipped {} records that couldn't be parsed", self.skipped),
+        }
+    }
+}
+
+/// Passes records through, handing any errors to rejected. Ends at the first error that
+/// rejected doesn't skip, storing it in error, wrapped with the input's name if it has one.
+pub(crate) fn skip_rejected<'a>(
+    records: impl Iterator<Item = Result<RequestRecord, Error>> + 'a,
+    input: Option<DefaultAtom>,
+    rejected: &'a RefCell<RejectedRecords>,
+    error: &'a RefCell<Option<Error>>,
+) -> impl Iterator<Item = RequestRecord> + 'a {
+    records
+        .map(move |record| {
+            let err = match record {
+                Ok(record) => return Some(Some(record)),
+                Err(err) => err,
+            };
+            match rejected.borrow_mut().handle(input.as_deref(), err) {
+                Ok(()) => Some(None),
+                Err(err) => {
+                    error.borrow_mut().get_or_insert(match &input {
+                        Some(name) => Error::Input {
+                            name: name.to_string(),
+                            source: Box::new(err),
+                        },
+                        None => err,
+                    });
+                    None
+                }
+            }
+        })
+        .map_while(|record| record)
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+    use crate::CommonLogReader;
+
+    fn input() -> Cursor<&'static str> {
+        Cursor::new(
+            r#"10.0.0.2 - - [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+not a log line
+10.0.0.2 - - [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0" 200 1234
+"#,
+        )
+    }
+
+    fn read(policy: ErrorPolicy) -> (Vec<u32>, Option<Error>, u64) {
+        let rejected = RefCell::new(RejectedRecords::new(policy));
+        let error = RefCell::new(None);
+        let dates = skip_rejected(CommonLogReader::new(input()), None, &rejected, &error)
+            .map(|record| record.date)
+            .collect();
+        let skipped = rejected.borrow().skipped;
+        (dates, error.into_inner(), skipped)
+    }
+
+    #[test]
+    fn test_policies() {
+        let (dates, error, skipped) = read(ErrorPolicy::Skip);
+        assert_eq!(dates, vec![1549573860, 1549573861]);
+        assert!(error.is_none());
+        assert_eq!(skipped, 1);
+
+        let (dates, error, _) = read(ErrorPolicy::Fail);
+        assert_eq!(dates, vec![1549573860]);
+        assert_eq!(
+            error.unwrap().to_string(),
+            "invalid record at line 2, byte 76"
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "http-monitor-dead-letters-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let (dates, error, skipped) = read(ErrorPolicy::DeadLetter(path.clone()));
+        assert_eq!(dates, vec![1549573860, 1549573861]);
+        assert!(error.is_none());
+        assert_eq!(skipped, 1);
+        let dead_letters = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            dead_letters,
+            "{\"line\":2,\"offset\":76,\"error\":\"missing field request\"}\n"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::{fmt, str::FromStr};
+
+use chrono::NaiveDateTime;
+use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{MonitorEvent, StatsWindow, UserAlert};
+
+/// A supported format of monitor output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Human-readable text, as formatted by [render_text].
+    #[default]
+    Text,
+    /// JSON Lines, with one object per event, as serialized from [MonitorEvent].
+    Json,
+}
+
+impl OutputFormat {
+    /// All of the supported formats.
+    pub const ALL: [OutputFormat; 2] = [OutputFormat::Text, OutputFormat::Json];
+
+    /// The name of this format, as used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    /// Formats an event as lines of output in this format.
+    pub fn render(self, event: &MonitorEvent) -> Vec<String> {
+        match self {
+            OutputFormat::Text => render_text(event),
+            OutputFormat::Json => vec![render_json(event)],
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown output format {:?}, expected one of: {}",
+                    name,
+                    Self::ALL
+                        .iter()
+                        .map(|format| format.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+/// Formats an event as a single line of JSON.
+pub fn render_json(event: &MonitorEvent) -> String {
+    serde_json::to_string(event).expect("events should always be serializable")
+}
+
+/// Formats an event as lines of human-readable text, as this program writes to its output.
+pub fn render_text(event: &MonitorEvent) -> Vec<String> {
+    match event {
+        MonitorEvent::Stats(stats) => render_stats(stats),
+        MonitorEvent::AlertTriggered(alert) => vec![format!(
+            "{} ALERT-----+------> average of {:5.1}rps over last {:3} seconds exceeds threshold of  {:5.1}rps <-------ALERT",
+            date(alert.date), alert.average_rate, alert.window_seconds, alert.threshold_rate as f64
+        )],
+        MonitorEvent::AlertRecovered(alert) => vec![format!(
+            "{} RECOVERY--+------> average of {:5.1}rps over last {:3} seconds is below threshold of {:5.1}rps <----RECOVERY",
+            date(alert.date), alert.average_rate, alert.window_seconds, alert.threshold_rate as f64
+        )],
+        MonitorEvent::UserAlertTriggered(UserAlert { user, alert }) => vec![format!(
+            "{} USER-ALERT+------> user {} averaged {:5.1}rps over last {:3} seconds, exceeding threshold of  {:5.1}rps <--USER-ALERT",
+            date(alert.date), user, alert.average_rate, alert.window_seconds, alert.threshold_rate as f64
+        )],
+        MonitorEvent::UserAlertRecovered(UserAlert { user, alert }) => vec![format!(
+            "{} USER-RECOVERY----> user {} averaged {:5.1}rps over last {:3} seconds, below threshold of {:5.1}rps <-USER-RECOVERY",
+            date(alert.date), user, alert.average_rate, alert.window_seconds, alert.threshold_rate as f64
+        )],
+    }
+}
+
+fn render_stats(stats: &StatsWindow) -> Vec<String> {
+    let start = date(stats.start);
+    let end = date(stats.end).time();
+
+    if stats.request_count == 0 {
+        return vec![format!("{}-{}  |  no requests", start, end)];
+    }
+
+    let percent = |count: u64| 100 * count / stats.request_count;
+
+    let top_sections = stats
+        .sections
+        .iter()
+        .take(1)
+        .map(|(section, count)| format!("{:3}% in {:<11}", percent(*count), section))
+        .join(", ");
+
+    let top_status_codes = stats
+        .status_codes
+        .iter()
+        .take(3)
+        .map(|(code, count)| format!("{:3}% {:03}", percent(*count), code))
+        .join(", ");
+
+    let mut output = vec![format!(
+        "{}-{}  |  {:4} requests at {:5.1}rps  |  {}  |  {}",
+        start, end, stats.request_count, stats.request_rate, top_sections, top_status_codes
+    )];
+
+    let total = stats.request_count;
+
+    // A breakdown where every request has the same value, like GET, would only be noise.
+    if stats.methods.len() > 1 {
+        output.push(format!(
+            "    by method:        {}",
+            top_counts(&stats.methods, total, 3)
+        ));
+    }
+    if stats.protocols.len() > 1 {
+        output.push(format!(
+            "    by protocol:      {}",
+            top_counts(&stats.protocols, total, 3)
+        ));
+    }
+    if !stats.query_sections.is_empty() {
+        output.push(format!(
+            "    with queries:     {}",
+            top_counts(&stats.query_sections, total, 3)
+        ));
+    }
+    // Only formats with these fields will have any counts here,
+    // so these lines are omitted for CSV or Common Log Format input by default.
+    if !stats.user_agents.is_empty() {
+        output.push(format!(
+            "    top user agents:  {}",
+            top_counts(&stats.user_agents, total, 3)
+        ));
+    }
+    if !stats.referers.is_empty() {
+        output.push(format!(
+            "    top referers:     {}",
+            top_counts(&stats.referers, total, 3)
+        ));
+    }
+    // Records are only tagged with their source if we're merging several inputs.
+    if !stats.sources.is_empty() {
+        output.push(format!(
+            "    by source:        {}",
+            top_counts(&stats.sources, total, usize::MAX)
+        ));
+    }
+
+    output
+}
+
+/// Formats up to limit of the most frequent values in counts, as percentages of total.
+fn top_counts<T: fmt::Display>(counts: &[(T, u64)], total: u64, limit: usize) -> String {
+    counts
+        .iter()
+        .take(limit)
+        .map(|(value, count)| format!("{:3}% {}", 100 * count / total, value))
+        .join(",  ")
+}
+
+fn date(timestamp: u32) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(timestamp.into(), 0)
+}
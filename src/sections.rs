@@ -0,0 +1,240 @@
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::RequestRecord;
+
+/// How request paths are grouped into sections, for stats and metrics.
+///
+/// The rules are applied to the path first, then each segment is checked against the
+/// detectors, and finally the path is cut down to the section's depth. With the defaults, the
+/// section is just the first segment, like `/api` for `/api/user`.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SectionConfig {
+    /// The number of leading path segments in a section, like 2 for `/api/user`.
+    pub depth: u32,
+    /// Detectors for variable path segments, which replace any segment they match with a
+    /// placeholder, like `/users/{id}/orders` for `/users/12345/orders`. Tried in order.
+    pub detectors: Vec<SegmentDetector>,
+    /// Regular expression rules that rewrite the path, applied in order before the detectors.
+    pub rules: Vec<PathRule>,
+}
+
+impl Default for SectionConfig {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            detectors: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// A built-in detector for a kind of variable path segment.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentDetector {
+    /// Numeric IDs, like `12345`, replaced by `{id}`.
+    Id,
+    /// UUIDs, like `123e4567-e89b-12d3-a456-426614174000`, replaced by `{uuid}`.
+    Uuid,
+    /// Hexadecimal hashes of at least 16 digits, like git or content hashes, replaced by
+    /// `{hash}`.
+    Hash,
+}
+
+impl SegmentDetector {
+    /// All of the detectors, in the order they're most usefully tried.
+    pub const ALL: [SegmentDetector; 3] = [
+        SegmentDetector::Id,
+        SegmentDetector::Uuid,
+        SegmentDetector::Hash,
+    ];
+
+    /// The placeholder that replaces the segments this detects.
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            SegmentDetector::Id => "{id}",
+            SegmentDetector::Uuid => "{uuid}",
+            SegmentDetector::Hash => "{hash}",
+        }
+    }
+
+    /// Whether segment is a variable of this kind.
+    pub fn detects(self, segment: &str) -> bool {
+        let is_hex = |part: &str| part.bytes().all(|b| b.is_ascii_hexdigit());
+        match self {
+            SegmentDetector::Id => {
+                !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit())
+            }
+            SegmentDetector::Uuid => {
+                let parts = segment.split('-').collect::<Vec<_>>();
+                parts
+                    .iter()
+                    .map(|part| part.len())
+                    .eq([8, 4, 4, 4, 12].iter().copied())
+                    && parts.iter().all(|part| is_hex(part))
+            }
+            SegmentDetector::Hash => segment.len() >= 16 && is_hex(segment),
+        }
+    }
+}
+
+/// A rule that rewrites request paths matching a regular expression.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PathRule {
+    /// The regular expression to match in the path, like `^/users/[^/]+`.
+    pub pattern: String,
+    /// The text to replace each match with, which may refer to capture groups like `$1`.
+    pub replacement: String,
+}
+
+/// Finds the section of each request, according to a [SectionConfig].
+#[derive(Debug, Clone)]
+pub struct Sections {
+    depth: usize,
+    detectors: Vec<SegmentDetector>,
+    rules: Vec<(Regex, String)>,
+}
+
+impl Sections {
+    /// Compiles the rules in config. Rules with invalid patterns are logged and skipped, as
+    /// they're reported by [Config::validate](crate::Config::validate).
+    pub fn new(config: &SectionConfig) -> Self {
+        let rules = config
+            .rules
+            .iter()
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some((regex, rule.replacement.clone())),
+                Err(error) => {
+                    log::warn!("skipping invalid path rule {:?}: {}", rule.pattern, error);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            depth: config.depth as usize,
+            detectors: config.detectors.clone(),
+            rules,
+        }
+    }
+
+    /// The section of a request, with a leading slash, like `/api` or `/users/{id}`, or None
+    /// if its request line is malformed.
+    pub fn section(&self, record: &RequestRecord) -> Option<String> {
+        let mut path = record.request.path()?.to_string();
+        for (regex, replacement) in &self.rules {
+            path = regex.replace_all(&path, replacement.as_str()).into_owned();
+        }
+
+        let segments = path.strip_prefix('/').unwrap_or(&path).split('/');
+        let mut section = String::with_capacity(path.len() + 1);
+        for segment in segments.take(self.depth) {
+            section.push('/');
+            section.push_str(
+                match self
+                    .detectors
+                    .iter()
+                    .find(|detector| detector.detects(segment))
+                {
+                    Some(detector) => detector.placeholder(),
+                    None => segment,
+                },
+            );
+        }
+        Some(section)
+    }
+}
+
+/// Checks that pattern is a valid regular expression, describing the problem if it isn't.
+pub(crate) fn check_pattern(pattern: &str) -> Result<(), String> {
+    Regex::new(pattern)
+        .map(|_| ())
+        .map_err(|error| format!("must be a valid regular expression: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::tests::record;
+
+    fn section(config: &SectionConfig, request: &str) -> Option<String> {
+        Sections::new(config).section(&record(request))
+    }
+
+    #[test]
+    fn test_depth() {
+        let config = SectionConfig::default();
+        assert_eq!(section(&config, "GET /api/user HTTP/1.0").unwrap(), "/api");
+        assert_eq!(section(&config, "GET / HTTP/1.0").unwrap(), "/");
+        assert_eq!(section(&config, "-"), None);
+
+        let config = SectionConfig {
+            depth: 2,
+            ..SectionConfig::default()
+        };
+        assert_eq!(
+            section(&config, "GET /api/user HTTP/1.0").unwrap(),
+            "/api/user"
+        );
+        assert_eq!(
+            section(&config, "GET /api/help?q=1 HTTP/1.0").unwrap(),
+            "/api/help"
+        );
+        assert_eq!(section(&config, "GET /report HTTP/1.0").unwrap(), "/report");
+    }
+
+    #[test]
+    fn test_detectors() {
+        let config = SectionConfig {
+            depth: 3,
+            detectors: SegmentDetector::ALL.to_vec(),
+            ..SectionConfig::default()
+        };
+        assert_eq!(
+            section(&config, "GET /users/12345/orders HTTP/1.1").unwrap(),
+            "/users/{id}/orders"
+        );
+        assert_eq!(
+            section(
+                &config,
+                "GET /files/123e4567-e89b-12d3-a456-426614174000 HTTP/1.1"
+            )
+            .unwrap(),
+            "/files/{uuid}"
+        );
+        assert_eq!(
+            section(
+                &config,
+                "GET /blobs/9fceb02d0ae598e95dc970b74767f19372d61af8/raw HTTP/1.1"
+            )
+            .unwrap(),
+            "/blobs/{hash}/raw"
+        );
+        assert_eq!(
+            section(&config, "GET /v2/cafe/beef HTTP/1.1").unwrap(),
+            "/v2/cafe/beef"
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = SectionConfig {
+            depth: 2,
+            rules: vec![PathRule {
+                pattern: "^/u/([a-z]+)".to_string(),
+                replacement: "/users/{name}".to_string(),
+            }],
+            ..SectionConfig::default()
+        };
+        assert_eq!(
+            section(&config, "GET /u/alice/settings HTTP/1.1").unwrap(),
+            "/users/{name}"
+        );
+
+        assert!(check_pattern("^/u/([a-z]+)").is_ok());
+        assert!(check_pattern("^/u/(").is_err());
+    }
+}
@@ -1,31 +1,200 @@
 use std::{
-    cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
+    cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd, Reverse},
     collections::{BinaryHeap, VecDeque},
-    iter::{Enumerate, FusedIterator},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    iter::FusedIterator,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
 
-use crate::{Config, RequestRecord};
+use crate::{Config, RequestRecord, MAX_TIMESTAMP_ERROR};
 
 /// Wraps an iterator of RequestRecords with a buffer to allow the records to be sorted.
 ///
-/// Panics if a request has a timestamp more than buffer_seconds less than the
-/// greatest previous timestamp.
+/// Records with a timestamp more than buffer_seconds less than the greatest previous
+/// timestamp are handled according to the config's late_policy. If that's
+/// [LatePolicy::Fail], iteration ends at the first late record, with the error available from
+/// [SortedRequestIterator::error].
 #[derive(Debug)]
 pub struct SortedRequestIterator<T: Iterator<Item = RequestRecord>> {
     /// The iterator being wrapped.
-    iterator: Enumerate<T>,
-    /// Records whose order is now known, but haven't yet been read out of this iterator.
+    iterator: T,
+    /// The records read from the iterator that haven't yet been read out of this iterator.
+    buffer: SortingBuffer,
+    /// The error that ended iteration, if any.
+    error: Option<LateRecordError>,
+}
+
+/// What to do with a record that's too far out of order to be sorted into place, because
+/// records after its timestamp have already been released.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatePolicy {
+    /// Skip the record.
+    Drop,
+    /// Change the record's timestamp to the earliest that's still in order.
+    #[default]
+    Clamp,
+    /// Skip the record, appending it as a line of JSON to the file at this path.
+    Stream(PathBuf),
+    /// Stop reading with an error.
+    Fail,
+}
+
+impl fmt::Display for LatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LatePolicy::Drop => f.write_str("drop"),
+            LatePolicy::Clamp => f.write_str("clamp"),
+            LatePolicy::Stream(path) => write!(f, "stream={}", path.display()),
+            LatePolicy::Fail => f.write_str("fail"),
+        }
+    }
+}
+
+impl FromStr for LatePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "drop" => Ok(LatePolicy::Drop),
+            "clamp" => Ok(LatePolicy::Clamp),
+            "fail" => Ok(LatePolicy::Fail),
+            _ => match value.strip_prefix("stream=") {
+                Some(path) if !path.is_empty() => Ok(LatePolicy::Stream(path.into())),
+                _ => Err(format!(
+                    "unknown late record policy {:?}, expected one of: drop, clamp, stream=PATH, fail",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/// An error from a record that was too far out of order to be sorted into place.
+#[derive(Debug, thiserror::Error)]
+pub enum LateRecordError {
+    /// The late policy is [LatePolicy::Fail].
+    #[error("record at {date} is {delay} seconds behind records that have already been output, more than the maximum timestamp error allows for")]
+    Late {
+        /// The timestamp of the late record.
+        date: u32,
+        /// How many seconds it's behind the records that have already been released.
+        delay: u32,
+    },
+    /// The late policy is [LatePolicy::Stream], and the record couldn't be written.
+    #[error("failed to write late record to {}", .path.display())]
+    Stream {
+        /// The path of the late record stream.
+        path: PathBuf,
+        /// Why the write failed.
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// A buffer that records are pushed into in roughly chronological order, and taken out of in
+/// exact chronological order once they're more than buffer_seconds older than the latest time
+/// that we've seen.
+#[derive(Debug)]
+pub(crate) struct SortingBuffer {
+    /// Records whose order is now known, but haven't yet been taken out of this buffer.
     /// (Timestamps will be more than buffer_seconds before largest_timestamp.)
     sorted: VecDeque<RequestRecord>,
     /// Records whose order is still indeterminate.
     /// (Timestamps will be within buffer_seconds of largest_timestamp.)
-    unsorted: BinaryHeap<ChronologicalRecord>,
+    /// Wrapped in Reverse so that the earliest record is at the top of the heap.
+    unsorted: BinaryHeap<Reverse<ChronologicalRecord>>,
     /// The number of seconds of logs to buffer.
     buffer_seconds: u32,
-    /// The maximum timestamp from records we've read from the iterator so far.
+    /// The maximum timestamp from records we've been given so far, or that we've been told
+    /// the time has advanced to.
     largest_timestamp: u32,
+    /// Counts of the records that have been pushed into this buffer.
+    stats: SortingStats,
+    /// The measurements for choosing buffer_seconds, while we're still warming up.
+    tuning: Option<Tuning>,
+    /// What to do with late records.
+    late_policy: LatePolicy,
+    /// The file that late records are written to, once there are any.
+    late_stream: Option<LineWriter<File>>,
+}
+
+/// Configuration for choosing how long to buffer records for sorting from how out of order
+/// they are, instead of from the config's maximum_timestamp_error.
+///
+/// Every record is buffered during a warm-up period, measuring how late each one is relative
+/// to the latest timestamp before it. The buffer is then sized to cover the given percentile
+/// of those delays.
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimestampErrorTuning {
+    /// The number of seconds of timestamps to measure before choosing the buffer size.
+    pub warm_up_seconds: u32,
+    /// The percentage of records in the warm-up whose delay the buffer should cover, from 1
+    /// to 100.
+    pub percentile: u8,
+}
+
+impl Default for TimestampErrorTuning {
+    fn default() -> Self {
+        Self {
+            warm_up_seconds: 60,
+            percentile: 99,
+        }
+    }
+}
+
+/// The state of a SortingBuffer that's measuring delays to choose its size.
+#[derive(Debug)]
+struct Tuning {
+    config: TimestampErrorTuning,
+    /// The timestamp of the first record, when the warm-up started.
+    start: Option<u32>,
+    /// How many seconds each record was behind the latest timestamp before it.
+    delays: Vec<u32>,
+}
+
+/// Counts of the records that have been sorted by timestamp.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SortingStats {
+    /// The number of records read.
+    pub read: u64,
+    /// The number of records read with an earlier timestamp than a previous record, that were
+    /// then sorted into place.
+    pub reordered: u64,
+    /// The number of records read with a timestamp too far before a previous record for them
+    /// to be sorted into place, which are handled according to the late policy.
+    pub late: u64,
+    /// The number of late records that were dropped.
+    pub dropped: u64,
+    /// The number of late records whose timestamps were clamped to keep them in order.
+    pub clamped: u64,
+    /// The number of late records that were written to the late record stream.
+    pub streamed: u64,
+    /// The most seconds that a late record was behind the records already released.
+    pub max_delay: u32,
+}
+
+impl std::ops::Add for SortingStats {
+    type Output = SortingStats;
+
+    fn add(self, other: SortingStats) -> SortingStats {
+        SortingStats {
+            read: self.read + other.read,
+            reordered: self.reordered + other.reordered,
+            late: self.late + other.late,
+            dropped: self.dropped + other.dropped,
+            clamped: self.clamped + other.clamped,
+            streamed: self.streamed + other.streamed,
+            max_delay: self.max_delay.max(other.max_delay),
+        }
+    }
 }
 
 /// A request record wrapped to sort by its date then (for stability) an index.
@@ -47,58 +216,261 @@ impl Ord for ChronologicalRecord {
     }
 }
 
-impl<T: Iterator<Item = RequestRecord>> SortedRequestIterator<T> {
-    pub fn new(iterator: T, config: &Config) -> Self {
+impl SortingBuffer {
+    /// Creates an empty buffer, sized according to the config's maximum_timestamp_error, or
+    /// to be sized after a warm-up if the config has timestamp_error_tuning.
+    pub(crate) fn new(config: &Config) -> Self {
         Self {
-            iterator: iterator.enumerate(),
             buffer_seconds: 2 * config.maximum_timestamp_error,
             largest_timestamp: 0,
             sorted: VecDeque::new(),
             unsorted: BinaryHeap::new(),
+            stats: SortingStats::default(),
+            tuning: config.timestamp_error_tuning.clone().map(|config| Tuning {
+                config,
+                start: None,
+                delays: Vec::new(),
+            }),
+            late_policy: config.late_policy.clone(),
+            late_stream: None,
         }
     }
-}
 
-impl<T: Iterator<Item = RequestRecord>> Iterator for SortedRequestIterator<T> {
-    type Item = RequestRecord;
+    /// Adds a record to the buffer, which may allow earlier records to be taken out.
+    ///
+    /// If the record is late, it's handled according to the late policy, which may fail.
+    pub(crate) fn push(&mut self, mut record: RequestRecord) -> Result<(), LateRecordError> {
+        let date = record.date;
+        let index = self.stats.read as usize;
 
-    fn next(&mut self) -> Option<RequestRecord> {
-        while self.sorted.is_empty() {
-            let next = self.iterator.next();
-
-            if next.is_none() {
-                // If there are no more items in the source iterator,
-                if self.unsorted.is_empty() {
-                    // then if there are also no unsorted items, this iterator is also exhausted.
-                    return None;
-                } else {
-                    // otherwise we can now take the remaining unsorted items and sort them.
-                    for record in self.unsorted.drain().sorted() {
-                        self.sorted.push_back(record.record);
+        if let Some(tuning) = &mut self.tuning {
+            tuning.start.get_or_insert(date);
+            tuning
+                .delays
+                .push(self.largest_timestamp.saturating_sub(date));
+        }
+
+        // There's nothing for the first record to be out of order with.
+        let released_before = self.released_before();
+        let late = self.stats.read > 0 && self.tuning.is_none() && date < released_before;
+        if self.stats.read > 0 && !late && date < self.largest_timestamp {
+            self.stats.reordered += 1;
+        }
+        self.stats.read += 1;
+
+        if late {
+            let delay = released_before - date;
+            self.stats.late += 1;
+            self.stats.max_delay = self.stats.max_delay.max(delay);
+
+            match &self.late_policy {
+                LatePolicy::Drop => {
+                    self.stats.dropped += 1;
+                    return Ok(());
+                }
+                LatePolicy::Clamp => {
+                    self.stats.clamped += 1;
+                    record.date = released_before;
+                }
+                LatePolicy::Stream(_) => {
+                    self.write_late(&record)?;
+                    self.stats.streamed += 1;
+                    return Ok(());
+                }
+                LatePolicy::Fail => return Err(LateRecordError::Late { date, delay }),
+            }
+        }
+
+        self.unsorted
+            .push(Reverse(ChronologicalRecord { record, index }));
+        self.advance_to(date);
+        Ok(())
+    }
+
+    /// Appends a late record to the late record stream, opening it if needed.
+    fn write_late(&mut self, record: &RequestRecord) -> Result<(), LateRecordError> {
+        let path = match &self.late_policy {
+            LatePolicy::Stream(path) => path,
+            _ => return Ok(()),
+        };
+
+        append_json(&mut self.late_stream, path, record).map_err(|source| LateRecordError::Stream {
+            path: path.clone(),
+            source,
+        })
+    }
+
+    /// Advances the latest time we've seen to timestamp, if it's later, releasing any records
+    /// that are now more than buffer_seconds older than it.
+    ///
+    /// Returns the timestamp before which every record should now have been released, so
+    /// that any later record before it is out-of-order by more than we allow for.
+    pub(crate) fn advance_to(&mut self, timestamp: u32) -> u32 {
+        if timestamp > self.largest_timestamp {
+            self.largest_timestamp = timestamp;
+
+            if let Some(tuning) = &self.tuning {
+                match tuning.start {
+                    Some(start) if timestamp - start >= tuning.config.warm_up_seconds => {
+                        self.finish_tuning()
                     }
+                    // Nothing is released until we've finished warming up.
+                    _ => return 0,
+                }
+            }
+
+            while let Some(Reverse(next)) = self.unsorted.peek() {
+                if next.record.date >= self.released_before() {
                     break;
                 }
+                self.sorted.push_back(self.unsorted.pop().unwrap().0.record);
             }
+        }
+
+        self.released_before()
+    }
+
+    /// The timestamp before which every record should have been released.
+    fn released_before(&self) -> u32 {
+        if self.tuning.is_some() {
+            return 0;
+        }
+        self.largest_timestamp.saturating_sub(self.buffer_seconds)
+    }
+
+    /// Ends the warm-up, sizing the buffer to cover the configured percentile of the delays
+    /// we've measured.
+    fn finish_tuning(&mut self) {
+        let tuning = match self.tuning.take() {
+            Some(tuning) => tuning,
+            None => return,
+        };
+        if tuning.delays.is_empty() {
+            return;
+        }
+
+        let mut delays = tuning.delays;
+        delays.sort_unstable();
+        let percentile = usize::from(tuning.config.percentile.clamp(1, 100));
+        let rank = (delays.len() * percentile).div_ceil(100);
+        let delay = delays[rank.max(1) - 1];
+
+        self.buffer_seconds = delay.min(2 * MAX_TIMESTAMP_ERROR);
+        log::info!(
+            "buffering records for {} seconds to sort them, covering {}% of the {} records in the warm-up, which were up to {} seconds out of order",
+            self.buffer_seconds,
+            percentile,
+            delays.len(),
+            delays[delays.len() - 1]
+        );
+    }
+
+    /// Counts of the records that have been pushed into this buffer.
+    pub(crate) fn stats(&self) -> SortingStats {
+        self.stats
+    }
+
+    /// Releases all of the remaining records, for when there won't be any more.
+    pub(crate) fn finish(&mut self) {
+        self.finish_tuning();
+        for Reverse(record) in self.unsorted.drain().sorted().rev() {
+            self.sorted.push_back(record.record);
+        }
+    }
+
+    /// Takes the earliest record out of the buffer, if its order is known.
+    pub(crate) fn pop(&mut self) -> Option<RequestRecord> {
+        self.sorted.pop_front()
+    }
+}
+
+/// Appends record as a line of JSON to the file at path, opening it into stream if it isn't
+/// already open.
+pub(crate) fn append_json(
+    stream: &mut Option<LineWriter<File>>,
+    path: &Path,
+    record: &impl serde::Serialize,
+) -> io::Result<()> {
+    let stream = match stream {
+        Some(stream) => stream,
+        None => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            stream.insert(LineWriter::new(file))
+        }
+    };
+    serde_json::to_writer(&mut *stream, record)?;
+    stream.write_all(b"\n")
+}
+
+impl<T: Iterator<Item = RequestRecord>> SortedRequestIterator<T> {
+    /// Wraps an iterator, buffering according to the config's maximum_timestamp_error.
+    pub fn new(iterator: T, config: &Config) -> Self {
+        Self {
+            iterator,
+            buffer: SortingBuffer::new(config),
+            error: None,
+        }
+    }
 
-            let (index, record) = next.unwrap();
+    /// Counts of the records that have been read from the wrapped iterator.
+    pub fn stats(&self) -> SortingStats {
+        self.buffer.stats()
+    }
+
+    /// The error from a late record that ended iteration, if any.
+    pub fn error(&self) -> Option<&LateRecordError> {
+        self.error.as_ref()
+    }
+}
+
+/// An iterator of chronologically-sorted records, which may end early because of a late
+/// record.
+pub(crate) trait SortedRecords: Iterator<Item = RequestRecord> {
+    /// Counts of the records that have been sorted.
+    fn stats(&self) -> SortingStats;
+
+    /// Takes the error from a late record that ended iteration, if any.
+    fn take_error(&mut self) -> Option<LateRecordError>;
+}
+
+impl<T: Iterator<Item = RequestRecord>> SortedRecords for SortedRequestIterator<T> {
+    fn stats(&self) -> SortingStats {
+        SortedRequestIterator::stats(self)
+    }
+
+    fn take_error(&mut self) -> Option<LateRecordError> {
+        self.error.take()
+    }
+}
 
-            let buffer_advanced = record.date > self.largest_timestamp;
-            if buffer_advanced {
-                self.largest_timestamp = record.date;
+impl<T: Iterator<Item = RequestRecord>> Iterator for SortedRequestIterator<T> {
+    type Item = RequestRecord;
+
+    fn next(&mut self) -> Option<RequestRecord> {
+        loop {
+            if let Some(record) = self.buffer.pop() {
+                return Some(record);
             }
 
-            self.unsorted.push(ChronologicalRecord { record, index });
+            if self.error.is_some() {
+                return None;
+            }
 
-            if buffer_advanced {
-                while self.unsorted.peek().unwrap().record.date
-                    < self.largest_timestamp - self.buffer_seconds
-                {
-                    self.sorted.push_back(self.unsorted.pop().unwrap().record);
+            match self.iterator.next() {
+                Some(record) => {
+                    if let Err(error) = self.buffer.push(record) {
+                        self.error = Some(error);
+                        return None;
+                    }
+                }
+                None => {
+                    // If there are no more items in the source iterator, we can now take the
+                    // remaining unsorted items and sort them.
+                    self.buffer.finish();
+                    return self.buffer.pop();
                 }
             }
         }
-
-        self.sorted.pop_front()
     }
 }
 
@@ -106,12 +478,127 @@ impl<T: Iterator<Item = RequestRecord>> FusedIterator for SortedRequestIterator<
 
 #[cfg(test)]
 mod tests {
+    use std::{cell::Cell, net::Ipv4Addr};
+
     use super::*;
 
+    fn record(date: u32) -> RequestRecord {
+        RequestRecord {
+            remote_host: Ipv4Addr::LOCALHOST.into(),
+            rfc931: (),
+            auth_user: None,
+            date,
+            request: "GET / HTTP/1.0".into(),
+            status: 200,
+            bytes: 0,
+            referer: None,
+            user_agent: None,
+            source: None,
+        }
+    }
+
     #[test]
     fn test_empty_iter() {
         let results: Vec<_> =
             SortedRequestIterator::new(Vec::new().into_iter(), &Config::default()).collect();
         assert_eq!(results, vec![]);
     }
+
+    #[test]
+    fn test_sorts_without_reading_ahead() {
+        let dates = [10, 12, 11, 13, 20, 16, 30];
+        let read = Cell::new(0);
+        let source = dates.iter().map(|date| {
+            read.set(read.get() + 1);
+            record(*date)
+        });
+
+        let mut sorted = SortedRequestIterator::new(source, &Config::default());
+
+        // Records are released once they're further behind the latest record than the
+        // buffer, rather than only when the source is exhausted.
+        assert_eq!(sorted.next().map(|record| record.date), Some(10));
+        assert_eq!(read.get(), 4);
+
+        // 16 is late, since 20 had already released everything before 18, so it's clamped.
+        let rest: Vec<_> = sorted.by_ref().map(|record| record.date).collect();
+        assert_eq!(rest, vec![11, 12, 13, 18, 20, 30]);
+
+        assert_eq!(
+            sorted.stats(),
+            SortingStats {
+                read: 7,
+                reordered: 1,
+                late: 1,
+                clamped: 1,
+                max_delay: 2,
+                ..SortingStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_late_policies() {
+        let dates = [10, 20, 12, 21, 15];
+        let sort = |late_policy: LatePolicy| {
+            let config = Config {
+                late_policy,
+                ..Config::default()
+            };
+            let mut sorted = SortedRequestIterator::new(dates.iter().map(|d| record(*d)), &config);
+            let dates: Vec<_> = sorted.by_ref().map(|record| record.date).collect();
+            (dates, sorted)
+        };
+
+        let (dropped, sorted) = sort(LatePolicy::Drop);
+        assert_eq!(dropped, vec![10, 20, 21]);
+        assert_eq!(sorted.stats().dropped, 2);
+        assert_eq!(sorted.stats().max_delay, 6);
+
+        let path = std::env::temp_dir().join(format!("http-monitor-late-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (streamed, sorted) = sort(LatePolicy::Stream(path.clone()));
+        assert_eq!(streamed, vec![10, 20, 21]);
+        assert_eq!(sorted.stats().streamed, 2);
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let late: Vec<u32> = lines
+            .lines()
+            .map(|line| serde_json::from_str::<RequestRecord>(line).unwrap().date)
+            .collect();
+        assert_eq!(late, vec![12, 15]);
+        std::fs::remove_file(&path).unwrap();
+
+        let (failed, sorted) = sort(LatePolicy::Fail);
+        assert_eq!(failed, vec![10]);
+        assert!(matches!(
+            sorted.error(),
+            Some(LateRecordError::Late { date: 12, delay: 6 })
+        ));
+    }
+
+    #[test]
+    fn test_tunes_buffer() {
+        // Every tenth record is 4 seconds behind the one before, and one is 29 seconds behind.
+        let mut dates: Vec<u32> = (100..200)
+            .map(|date| if date % 10 == 0 { date - 5 } else { date })
+            .collect();
+        dates[20] = 90;
+
+        let config = Config {
+            timestamp_error_tuning: Some(TimestampErrorTuning {
+                warm_up_seconds: 50,
+                percentile: 95,
+            }),
+            ..Config::default()
+        };
+        let mut sorted =
+            SortedRequestIterator::new(dates.iter().map(|date| record(*date)), &config);
+
+        assert_eq!(sorted.next().map(|record| record.date), Some(90));
+        assert_eq!(sorted.buffer.buffer_seconds, 4);
+
+        let rest: Vec<_> = sorted.by_ref().map(|record| record.date).collect();
+        assert!(rest.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(sorted.stats().late, 0);
+    }
 }
@@ -0,0 +1,419 @@
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Read},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+};
+
+/// The largest UDP datagram we'll accept. Longer syslog messages are truncated by senders.
+const MAX_DATAGRAM_LENGTH: usize = 65_535;
+
+/// The most log lines that can be waiting to be read. Once there are this many, messages
+/// received over UDP are dropped, and TCP connections wait.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Listens for syslog messages over UDP and TCP, such as from nginx's `access_log syslog:`
+/// directive, and reads the log line embedded in each one.
+///
+/// Messages may be in RFC 5424 or RFC 3164 (BSD) format. Over TCP, they may be framed by
+/// octet counting or by newlines, as described in RFC 6587. Reading returns the log lines
+/// from the messages, each terminated by a newline, so they can be parsed like the contents
+/// of a log file. Messages that aren't valid syslog are logged and skipped, as are messages
+/// received over UDP while too many are waiting to be read, which are counted. Reads block
+/// until there's another message, and never return EOF.
+#[derive(Debug)]
+pub struct SyslogListener {
+    /// The address of the TCP listener. The UDP socket is bound to the same address.
+    local_addr: SocketAddr,
+    /// The log lines received by the listener threads.
+    lines: Receiver<String>,
+    /// The number of messages dropped since it was last logged.
+    dropped: Arc<AtomicU64>,
+    /// The remainder of the line currently being read.
+    line: Cursor<Vec<u8>>,
+}
+
+impl SyslogListener {
+    /// Starts listening for syslog messages on addr, with both UDP and TCP.
+    ///
+    /// If addr has port 0, an available port is chosen for both.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let tcp = TcpListener::bind(addr)?;
+        let local_addr = tcp.local_addr()?;
+        let udp = UdpSocket::bind(local_addr)?;
+
+        log::info!(
+            "listening for syslog messages on {} (udp and tcp)",
+            local_addr
+        );
+
+        let (sender, lines) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let sender = LineSender {
+            sender,
+            dropped: Arc::default(),
+        };
+        let dropped = Arc::clone(&sender.dropped);
+
+        let udp_sender = sender.clone();
+        thread::spawn(move || receive_datagrams(udp, udp_sender));
+        thread::spawn(move || accept_connections(tcp, sender));
+
+        Ok(Self {
+            local_addr,
+            lines,
+            dropped,
+            line: Cursor::new(Vec::new()),
+        })
+    }
+
+    /// The address that we're listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Read for SyslogListener {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let length = self.line.read(buf)?;
+            if length > 0 || buf.is_empty() {
+                return Ok(length);
+            }
+
+            match self.lines.recv() {
+                Ok(mut line) => {
+                    let dropped = self.dropped.swap(0, Ordering::Relaxed);
+                    if dropped > 0 {
+                        log::warn!(
+                            "dropped {} syslog messages received over udp while too many were waiting to be read",
+                            dropped
+                        );
+                    }
+                    line.push('\n');
+                    self.line = Cursor::new(line.into_bytes());
+                }
+                // The listener threads only exit if their sockets fail.
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "stopped listening for syslog messages",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Parses a syslog message, returning the log line embedded in it.
+///
+/// Returns None if the message doesn't start with a syslog priority, like `<190>`.
+pub fn parse_syslog_message(message: &str) -> Option<&str> {
+    let message = message.trim_end_matches(&['\r', '\n', '\0'][..]);
+
+    let rest = message.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let priority = &rest[..end];
+    if priority.is_empty() || priority.len() > 3 || !priority.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let rest = &rest[end + 1..];
+
+    // RFC 5424 messages have a version number after the priority, where RFC 3164 messages
+    // have a month name.
+    let content = match rest.strip_prefix("1 ") {
+        Some(rest) => rfc5424_message(rest),
+        None => rfc3164_message(rest),
+    };
+
+    // RFC 5424 allows a byte order mark to indicate that the message is UTF-8.
+    Some(content.trim_start_matches('\u{feff}'))
+}
+
+/// Skips the header fields of an RFC 5424 message after its version, like
+/// `2019-02-07T21:11:00Z web-1 nginx - - - 10.0.0.2 - ...`.
+fn rfc5424_message(rest: &str) -> &str {
+    // The timestamp, hostname, app-name, procid and msgid are single tokens.
+    let mut rest = rest;
+    for _ in 0..5 {
+        rest = match rest.find(' ') {
+            Some(space) => &rest[space + 1..],
+            None => return "",
+        };
+    }
+
+    // The structured data is either "-" or a sequence of bracketed elements, which may
+    // contain escaped brackets in their quoted parameter values.
+    let data_length = if rest.starts_with('-') {
+        1
+    } else {
+        let mut depth = 0;
+        let mut in_quotes = false;
+        let mut escaped = false;
+        let mut length = rest.len();
+        for (index, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' if depth > 0 => in_quotes = !in_quotes,
+                '[' if !in_quotes => depth += 1,
+                ']' if !in_quotes => depth -= 1,
+                ' ' if depth == 0 => {
+                    length = index;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        length
+    };
+
+    rest.get(data_length + 1..).unwrap_or("")
+}
+
+/// Skips the header of an RFC 3164 message, like `Feb  7 21:11:00 web-1 nginx: 10.0.0.2 - ...`.
+///
+/// The header is loosely specified and often malformed, so if it doesn't have the expected
+/// timestamp we assume that it's missing and the whole message is content.
+fn rfc3164_message(rest: &str) -> &str {
+    const TIMESTAMP_LENGTH: usize = "Feb  7 21:11:00".len();
+
+    let has_timestamp = rest.len() > TIMESTAMP_LENGTH
+        && rest.is_char_boundary(TIMESTAMP_LENGTH)
+        && chrono::NaiveDateTime::parse_from_str(
+            &format!("2000 {}", &rest[..TIMESTAMP_LENGTH]),
+            "%Y %b %e %H:%M:%S",
+        )
+        .is_ok();
+    if !has_timestamp {
+        return rest;
+    }
+    let rest = &rest[TIMESTAMP_LENGTH + 1..];
+
+    // Then the hostname, then a tag like "nginx:" or "nginx[123]:".
+    let rest = match rest.find(' ') {
+        Some(space) => &rest[space + 1..],
+        None => return "",
+    };
+    match rest.find(": ") {
+        Some(colon)
+            if rest[..colon]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./[]".contains(c)) =>
+        {
+            &rest[colon + 2..]
+        }
+        _ => rest,
+    }
+}
+
+/// Sends log lines from the listener threads to the reader.
+#[derive(Debug, Clone)]
+struct LineSender {
+    sender: SyncSender<String>,
+    /// The number of lines dropped because too many were waiting to be read.
+    dropped: Arc<AtomicU64>,
+}
+
+impl LineSender {
+    /// Sends line, waiting for there to be room if wait is true, or otherwise dropping and
+    /// counting it if there isn't. Returns false if the reader has been dropped.
+    fn send(&self, line: String, wait: bool) -> bool {
+        if wait {
+            return self.sender.send(line).is_ok();
+        }
+        match self.sender.try_send(line) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Sends the log line from message to the reader, or logs it if it isn't valid syslog. If
+/// the reader is behind, waits for it if wait is true, or otherwise drops the line.
+///
+/// Returns false if the reader has been dropped, so there's no point receiving more.
+fn forward(message: &str, lines: &LineSender, wait: bool) -> bool {
+    match parse_syslog_message(message) {
+        Some(line) if line.trim().is_empty() => true,
+        Some(line) => lines.send(line.to_string(), wait),
+        None => {
+            log::warn!("skipping invalid syslog message {:?}", message);
+            true
+        }
+    }
+}
+
+fn receive_datagrams(socket: UdpSocket, lines: LineSender) {
+    let mut buffer = vec![0; MAX_DATAGRAM_LENGTH];
+    loop {
+        let length = match socket.recv(&mut buffer) {
+            Ok(length) => length,
+            Err(error) => {
+                log::error!("failed to receive syslog message over udp: {}", error);
+                return;
+            }
+        };
+
+        // Waiting wouldn't slow down the sender, only lose messages from the socket's buffer.
+        if !forward(&String::from_utf8_lossy(&buffer[..length]), &lines, false) {
+            return;
+        }
+    }
+}
+
+fn accept_connections(listener: TcpListener, lines: LineSender) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let lines = lines.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    if let Err(error) = receive_stream(stream, lines) {
+                        log::warn!(
+                            "syslog connection from {:?} failed: {}",
+                            peer.map(|peer| peer.to_string()),
+                            error
+                        );
+                    }
+                });
+            }
+            Err(error) => log::warn!("failed to accept syslog connection: {}", error),
+        }
+    }
+}
+
+/// Receives messages from a TCP connection until it's closed.
+///
+/// Each message is framed either by a decimal length prefix (octet counting), or by a
+/// trailing newline (non-transparent framing), which we can distinguish because messages
+/// always start with "<".
+fn receive_stream(stream: TcpStream, lines: LineSender) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut message = Vec::new();
+
+    loop {
+        message.clear();
+
+        let first = match stream.fill_buf()?.first() {
+            Some(first) => *first,
+            None => return Ok(()),
+        };
+
+        if first.is_ascii_digit() {
+            stream.read_until(b' ', &mut message)?;
+            let length: usize = String::from_utf8_lossy(&message)
+                .trim_end()
+                .parse()
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid syslog frame length")
+                })?;
+            message.clear();
+            (&mut stream)
+                .take(length as u64)
+                .read_to_end(&mut message)?;
+        } else {
+            stream.read_until(b'\n', &mut message)?;
+        }
+
+        if !forward(&String::from_utf8_lossy(&message), &lines, true) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::Ipv4Addr};
+
+    use super::*;
+
+    const LINE: &str =
+        r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#;
+
+    #[test]
+    fn test_parse_rfc5424() {
+        assert_eq!(
+            parse_syslog_message(&format!(
+                "<190>1 2019-02-07T21:11:00.000Z web-1 nginx 1234 - - {}",
+                LINE
+            )),
+            Some(LINE)
+        );
+        assert_eq!(
+            parse_syslog_message(&format!(
+                r#"<190>1 2019-02-07T21:11:00Z web-1 nginx - access [meta a="[\]" b="\""][origin ip="10.0.0.1"] {}"#,
+                LINE
+            )),
+            Some(LINE)
+        );
+        assert_eq!(
+            parse_syslog_message(&format!("<190>1 - - - - - - \u{feff}{}\n", LINE)),
+            Some(LINE)
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3164() {
+        assert_eq!(
+            parse_syslog_message(&format!("<190>Feb  7 21:11:00 web-1 nginx: {}", LINE)),
+            Some(LINE)
+        );
+        assert_eq!(
+            parse_syslog_message(&format!("<190>Feb 17 21:11:00 web-1 nginx[123]: {}", LINE)),
+            Some(LINE)
+        );
+        // Some senders omit the header entirely.
+        assert_eq!(parse_syslog_message(&format!("<13>{}", LINE)), Some(LINE));
+
+        assert_eq!(parse_syslog_message(LINE), None);
+        assert_eq!(parse_syslog_message("<abc>text"), None);
+    }
+
+    #[test]
+    fn test_listen() {
+        let listener = SyslogListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr();
+        let mut lines = BufReader::new(listener).lines();
+
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        udp.send_to(b"<190>Feb  7 21:11:00 web-1 nginx: one", addr)
+            .unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "one");
+
+        let mut tcp = TcpStream::connect(addr).unwrap();
+        let framed = "<190>1 2019-02-07T21:11:00Z web-1 nginx - - - two";
+        write!(tcp, "{} {}", framed.len(), framed).unwrap();
+        tcp.write_all(b"<190>Feb  7 21:11:00 web-1 nginx: three\n")
+            .unwrap();
+        tcp.write_all(b"not syslog\n<190>Feb  7 21:11:00 web-1 nginx: four\n")
+            .unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "two");
+        assert_eq!(lines.next().unwrap().unwrap(), "three");
+        assert_eq!(lines.next().unwrap().unwrap(), "four");
+    }
+
+    #[test]
+    fn test_drop_when_full() {
+        let (sender, lines) = mpsc::sync_channel(1);
+        let sender = LineSender {
+            sender,
+            dropped: Arc::default(),
+        };
+
+        assert!(sender.send("one".to_string(), false));
+        assert!(sender.send("two".to_string(), false));
+        assert_eq!(sender.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(lines.recv().unwrap(), "one");
+
+        drop(lines);
+        assert!(!sender.send("three".to_string(), false));
+    }
+}
@@ -1,10 +1,21 @@
 //! Integration tests for http-monitor, asserting the expected outputs for given inputs.
 
-use std::{io::Cursor, panic::catch_unwind, str};
-
-use anyhow;
-
-use http_monitor::{monitor_stream, Config};
+use std::{
+    io::{self, Cursor, Read, Write},
+    rc::Rc,
+    str,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use http_monitor::{
+    monitor_live, monitor_stream, monitor_streams, parse_common_log_line, render_text, Alert,
+    ChunkedStatsMonitor, Config, CsvColumn, CsvColumns, Error, ErrorPolicy, InputFormat,
+    LatePolicy, Monitor, MonitorEvent, OutputFormat, PathRule, RollingAlertsMonitor, WebhookConfig,
+};
 
 #[test]
 /// Tests with no input.
@@ -41,8 +52,8 @@ fn test_monitor_one_request() -> anyhow::Result<()> {
 
 #[test]
 fn test_monitor_sample_input() -> anyhow::Result<()> {
-    let input = &include_str!("../samples/input.csv")[..];
-    let expected = &include_str!("../samples/output.txt")[..];
+    let input = include_str!("../samples/input.csv");
+    let expected = include_str!("../samples/output.txt");
 
     let mut source = Cursor::new(input);
     let mut sink = Cursor::new(Vec::new());
@@ -123,15 +134,677 @@ fn test_monitor_invalid_csv_input_extra_column() -> anyhow::Result<()> {
 "10.0.0.1","-","apache",1549574334,"GET /api/user HTTP/1.0",200,1194
 "10.0.0.4","-","apache",1549574334,"POST /report HTTP/1.0",404,1307"#;
 
-    let result = catch_unwind(|| {
-        let mut source = Cursor::new(input);
-        let mut sink = Cursor::new(Vec::new());
-        let config = Config::default();
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    let result = monitor_stream(&mut source, &mut sink, &config);
+
+    // This is reported as bad input, rather than panicking as if it were a bug.
+    match result {
+        Err(error @ Error::Parse { .. }) => {
+            assert!(error.is_bad_input());
+            assert_eq!(error.to_string(), "invalid record at line 3, byte 136");
+        }
+        other => panic!("expected a parse error for record two, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+/// Tests that with the skip policy, a row that can't be parsed is left out, and the rest of the
+/// input is still monitored.
+fn test_monitor_skips_invalid_records() -> anyhow::Result<()> {
+    let input = r#""remotehost","rfc931","authuser","date","request","status","bytes"
+"10.0.0.1","-","apache",1549574332,"GET /api/user HTTP/1.0",200,1234
+"10.0.0.4","-","apache",1549574333,"GET /report HTTP/1.0",200,1136,10101,13513
+"10.0.0.1","-","apache",1549574334,"GET /api/user HTTP/1.0",200,1194
+"10.0.0.4","-","apache",1549574334,"POST /report HTTP/1.0",404,1307"#;
+    let expected = "2019-02-07 21:18:52-21:19:02  |     3 requests at   0.3rps  |   66% in /api         |   66% 200,  33% 404
+    by method:         66% GET,   33% POST
+";
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        on_error: Some(ErrorPolicy::Skip),
+        ..Config::default()
+    };
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    assert_eq!(str::from_utf8(&actual)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_common_log() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.4 - apache [07/Feb/2019:21:11:01 +0000] "GET /report?year=2019 HTTP/1.1" 500 1307
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:10  |     2 requests at   0.2rps  |   50% in /report      |   50% 500,  50% 200
+    by protocol:       50% HTTP/1.1,   50% HTTP/1.0
+    with queries:      50% /report
+";
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_invalid_common_log() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.4 - apache [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0"
+"#;
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        input_format: Some(InputFormat::Common),
+        ..Config::default()
+    };
+
+    let result = monitor_stream(&mut source, &mut sink, &config);
+
+    assert!(result.is_err(), "status and bytes missing on line 2");
+    Ok(())
+}
+
+#[test]
+fn test_monitor_combined_log() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234 "https://example.com/" "curl/7.64.0"
+10.0.0.4 - apache [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0" 500 1307 "-" "curl/7.64.0"
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:10  |     2 requests at   0.2rps  |   50% in /report      |   50% 500,  50% 200
+    top user agents:  100% curl/7.64.0
+    top referers:      50% https://example.com/
+";
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_specified_format() -> anyhow::Result<()> {
+    let input = include_str!("../samples/input.csv");
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        input_format: Some(InputFormat::Common),
+        ..Config::default()
+    };
+
+    let result = monitor_stream(&mut source, &mut sink, &config);
+
+    assert!(result.is_err(), "csv isn't common log format");
+    Ok(())
+}
+
+#[test]
+fn test_monitor_headers_only() -> anyhow::Result<()> {
+    let input = r#""remotehost","rfc931","authuser","date","request","status","bytes""#;
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    assert!(sink.into_inner().is_empty(), "no records, so no output");
+    Ok(())
+}
+
+#[test]
+fn test_monitor_json_lines() -> anyhow::Result<()> {
+    let input = r#"{"remote_addr": "10.0.0.2", "msec": 1549573860.5, "request": "GET /api/user HTTP/1.0", "status": 200, "body_bytes_sent": 1234}
+{"remote_addr": "10.0.0.4", "msec": 1549573861.5, "request_method": "GET", "request_uri": "/report", "status": 500, "body_bytes_sent": 1307}
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:10  |     2 requests at   0.2rps  |   50% in /report      |   50% 500,  50% 200\n";
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_csv_column_mapping() -> anyhow::Result<()> {
+    let input = r#"request_ts,client_ip,method_and_path,http_status,response_bytes,datacenter
+1549573860,10.0.0.2,GET /api/user HTTP/1.0,200,1234,us-east
+1549573861,10.0.0.4,GET /report HTTP/1.0,500,1307,us-west
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:10  |     2 requests at   0.2rps  |   50% in /report      |   50% 500,  50% 200\n";
+
+    let mut config = Config {
+        csv_columns: CsvColumns {
+            remote_host: "client_ip".into(),
+            date: "request_ts".into(),
+            request: CsvColumn::Index(2),
+            status: "http_status".into(),
+            bytes: "response_bytes".into(),
+            ..CsvColumns::default()
+        },
+        ..Config::default()
+    };
+
+    let result = monitor_stream(
+        &mut Cursor::new(input),
+        &mut Cursor::new(Vec::new()),
+        &config,
+    );
+    assert!(result.is_err(), "datacenter column is unknown");
+
+    config.csv_columns.ignore_unknown = true;
 
-        monitor_stream(&mut source, &mut sink, &config)
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_compressed_input() -> anyhow::Result<()> {
+    let input = include_str!("../samples/input.csv");
+    let expected = include_str!("../samples/output.txt");
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(input.as_bytes())?;
+
+    let mut source = Cursor::new(encoder.finish()?);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_monitor_merged_streams() -> anyhow::Result<()> {
+    let web_1 = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:03 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:11 +0000] "GET /api/user HTTP/1.0" 200 1234
+"#;
+    let web_2 = r#""remotehost","rfc931","authuser","date","request","status","bytes"
+"10.0.0.4","-","apache",1549573862,"GET /report HTTP/1.0",500,1307
+"10.0.0.4","-","apache",1549573861,"GET /report HTTP/1.0",500,1307
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:10  |     4 requests at   0.4rps  |   50% in /report      |   50% 500,  50% 200
+    by source:         50% web-2,   50% web-1
+2019-02-07 21:11:10-21:11:20  |     1 requests at   0.1rps  |  100% in /api         |  100% 200
+    by source:        100% web-1
+";
+
+    let sources: Vec<(String, Box<dyn Read>)> = vec![
+        ("web-1".to_string(), Box::new(Cursor::new(web_1))),
+        ("web-2".to_string(), Box::new(Cursor::new(web_2))),
+    ];
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config::default();
+
+    monitor_streams(sources, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+/// A live source that blocks until input is sent to it, and ends when the sender is dropped.
+struct ChannelSource(mpsc::Receiver<Vec<u8>>, Cursor<Vec<u8>>);
+
+impl Read for ChannelSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let length = self.1.read(buf)?;
+            if length > 0 {
+                return Ok(length);
+            }
+            match self.0.recv() {
+                Ok(input) => self.1 = Cursor::new(input),
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// A sink that forwards each write to a channel.
+struct ChannelSink(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+/// Tests that a late record under the fail policy stops the run, but only after the stats for
+/// the records before it have been output.
+fn test_monitor_late_fail() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:05 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:01 +0000] "GET /api/user HTTP/1.0" 200 1234
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:02  |     1 requests at   0.5rps  |  100% in /api         |  100% 200
+";
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        stats_window: 2,
+        maximum_timestamp_error: 0,
+        late_policy: LatePolicy::Fail,
+        ..Config::default()
+    };
+
+    let result = monitor_stream(&mut source, &mut sink, &config);
+    assert!(matches!(result, Err(Error::Late(_))), "{:?}", result);
+
+    let actual = sink.into_inner();
+    assert_eq!(str::from_utf8(&actual)?, expected);
+    Ok(())
+}
+
+#[test]
+/// Tests that a live stream's stats and alerts are output once time passes without any more
+/// records, before the stream has ended.
+fn test_monitor_live_ticks() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+"#;
+    let expected = "2019-02-07 21:11:00 ALERT-----+------> average of   1.0rps over last   2 seconds exceeds threshold of    1.0rps <-------ALERT
+2019-02-07 21:11:00-21:11:02  |     3 requests at   1.5rps  |  100% in /api         |  100% 200
+2019-02-07 21:11:02 RECOVERY--+------> average of   0.0rps over last   2 seconds is below threshold of   1.0rps <----RECOVERY
+";
+
+    let (input_sender, input_receiver) = mpsc::channel();
+    let (output_sender, output_receiver) = mpsc::channel();
+    let config = Config {
+        stats_window: 2,
+        alert_window: 2,
+        alert_rate: 1,
+        maximum_timestamp_error: 0,
+        ..Config::default()
+    };
+
+    let monitor = thread::spawn(move || {
+        let source = ChannelSource(input_receiver, Cursor::new(Vec::new()));
+        monitor_live(source, &mut ChannelSink(output_sender), &config)
     });
 
-    assert!(result.is_err(), "extra column in record two");
+    input_sender.send(input.as_bytes().to_vec())?;
+
+    // Further chunks with no requests will follow for as long as the stream stays open.
+    let mut actual = Vec::new();
+    while actual.len() < expected.len() {
+        actual.extend(output_receiver.recv_timeout(Duration::from_secs(10))?);
+    }
+    assert_eq!(str::from_utf8(&actual[..expected.len()])?, expected);
+
+    drop(input_sender);
+    monitor.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+/// Tests that a live stream carries on past records that can't be parsed, even before its
+/// format has been detected.
+fn test_monitor_live_skips_bad_records() -> anyhow::Result<()> {
+    let input = r#"not a log line
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+not a log line
+10.0.0.2 - apache [07/Feb/2019:21:11:01 +0000] "GET /api/help HTTP/1.0" 200 1234
+"#;
+    let expected = "2019-02-07 21:11:00-21:11:02  |     2 requests at   1.0rps  |  100% in /api         |  100% 200
+";
+
+    let config = Config {
+        stats_window: 2,
+        alert_window: 2,
+        alert_rate: 10,
+        maximum_timestamp_error: 0,
+        ..Config::default()
+    };
+    let mut sink = Cursor::new(Vec::new());
+    monitor_live(input.as_bytes(), &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    assert_eq!(str::from_utf8(&actual)?, expected);
+    Ok(())
+}
+
+#[test]
+/// Tests consuming the monitors' events directly, rather than their text output.
+fn test_monitor_events() -> anyhow::Result<()> {
+    let config = Config {
+        stats_window: 2,
+        alert_window: 2,
+        alert_rate: 1,
+        ..Config::default()
+    };
+    let mut stats = ChunkedStatsMonitor::from_config(&config);
+    let mut alerts = RollingAlertsMonitor::from_config(&config);
+
+    let mut events = Vec::new();
+    for line in &[
+        r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#,
+        r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234"#,
+        r#"10.0.0.2 - apache [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0" 500 1234"#,
+        r#"10.0.0.2 - apache [07/Feb/2019:21:11:05 +0000] "GET /report HTTP/1.0" 200 1234"#,
+    ] {
+        let record = Rc::new(parse_common_log_line(line)?);
+        events.extend(stats.push(&record)?);
+        events.extend(alerts.push(&record)?);
+    }
+
+    let window = match &events[..] {
+        [MonitorEvent::AlertTriggered(triggered), MonitorEvent::Stats(window), MonitorEvent::Stats(empty), MonitorEvent::AlertRecovered(recovered)] =>
+        {
+            assert_eq!(
+                *triggered,
+                Alert {
+                    date: 1549573860,
+                    request_count: 2,
+                    average_rate: 1.0,
+                    window_seconds: 2,
+                    threshold_rate: 1,
+                }
+            );
+            assert_eq!(empty.request_count, 0);
+            assert_eq!(recovered.date, 1549573865);
+            assert_eq!(recovered.request_count, 1);
+            window
+        }
+        events => panic!("unexpected events {:?}", events),
+    };
+
+    assert_eq!((window.start, window.end), (1549573860, 1549573862));
+    assert_eq!(window.request_count, 3);
+    assert_eq!(window.request_rate, 1.5);
+    assert_eq!(
+        window.sections,
+        vec![("/api".to_string(), 2), ("/report".to_string(), 1)]
+    );
+    assert_eq!(window.status_codes, vec![(200, 2), (500, 1)]);
+    assert!(window.user_agents.is_empty());
+
+    assert_eq!(
+        render_text(&events[1]),
+        vec!["2019-02-07 21:11:00-21:11:02  |     3 requests at   1.5rps  |   66% in /api         |   66% 200,  33% 500"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_monitor_json_output() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:00 +0000] "GET /api/user?id=2 HTTP/1.0" 200 1234
+10.0.0.2 - apache [07/Feb/2019:21:11:03 +0000] "GET /report HTTP/1.0" 500 1234
+"#;
+    let expected = vec![
+        serde_json::json!({
+            "type": "alert_triggered",
+            "date": "2019-02-07T21:11:00Z",
+            "request_count": 2,
+            "average_rate": 1.0,
+            "window_seconds": 2,
+            "threshold_rate": 1,
+        }),
+        serde_json::json!({
+            "type": "alert_recovered",
+            "date": "2019-02-07T21:11:03Z",
+            "request_count": 1,
+            "average_rate": 0.5,
+            "window_seconds": 2,
+            "threshold_rate": 1,
+        }),
+        serde_json::json!({
+            "type": "stats",
+            "start": "2019-02-07T21:11:00Z",
+            "end": "2019-02-07T21:11:10Z",
+            "request_count": 3,
+            "request_rate": 0.3,
+            "malformed_count": 0,
+            "sections": [{"value": "/api", "count": 2}, {"value": "/report", "count": 1}],
+            "status_codes": [{"value": 200, "count": 2}, {"value": 500, "count": 1}],
+            "query_sections": [{"value": "/api", "count": 1}],
+            "methods": [{"value": "GET", "count": 3}],
+            "protocols": [{"value": "HTTP/1.0", "count": 3}],
+            "user_agents": [],
+            "referers": [],
+            "sources": [],
+        }),
+    ];
+
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        alert_window: 2,
+        alert_rate: 1,
+        output_format: OutputFormat::Json,
+        ..Config::default()
+    };
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+/// Tests alerting on a single user's request rate, while the overall rate stays below its
+/// threshold.
+fn test_monitor_user_alerts() -> anyhow::Result<()> {
+    let input = r#"10.0.0.2 - alice [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.3 - bob [07/Feb/2019:21:11:00 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.2 - alice [07/Feb/2019:21:11:01 +0000] "GET /api/user HTTP/1.0" 200 1234
+10.0.0.4 - - [07/Feb/2019:21:11:01 +0000] "GET /report HTTP/1.0" 200 1234
+10.0.0.2 - alice [07/Feb/2019:21:11:05 +0000] "GET /api/user HTTP/1.0" 200 1234
+"#;
+    let expected = vec![
+        serde_json::json!({
+            "type": "user_alert_triggered",
+            "user": "alice",
+            "date": "2019-02-07T21:11:01Z",
+            "request_count": 2,
+            "average_rate": 1.0,
+            "window_seconds": 2,
+            "threshold_rate": 1,
+        }),
+        serde_json::json!({
+            "type": "user_alert_recovered",
+            "user": "alice",
+            "date": "2019-02-07T21:11:05Z",
+            "request_count": 1,
+            "average_rate": 0.5,
+            "window_seconds": 2,
+            "threshold_rate": 1,
+        }),
+    ];
 
+    let mut source = Cursor::new(input);
+    let mut sink = Cursor::new(Vec::new());
+    let config = Config {
+        alert_window: 2,
+        alert_rate: 10,
+        keep_auth_user: true,
+        user_alert_rate: Some(1),
+        output_format: OutputFormat::Json,
+        ..Config::default()
+    };
+
+    monitor_stream(&mut source, &mut sink, &config)?;
+
+    let actual = sink.into_inner();
+    let actual = str::from_utf8(&actual)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?
+        .into_iter()
+        .filter(|event| event["type"] != "stats")
+        .collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn test_load_config() -> anyhow::Result<()> {
+    assert_eq!(Config::load("samples/config.toml")?, Config::default());
+
+    let dir = std::env::temp_dir().join(format!("http-monitor-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let write = |name: &str, contents: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    };
+
+    let expected = Config {
+        stats_window: 30,
+        input_format: Some(InputFormat::Common),
+        csv_columns: CsvColumns {
+            date: CsvColumn::Index(1),
+            ..CsvColumns::default()
+        },
+        ..Config::default()
+    };
+    let toml = write(
+        "config.toml",
+        "stats_window = 30\ninput_format = \"common\"\n[csv_columns]\ndate = 1\n",
+    );
+    let json = write(
+        "config.json",
+        r#"{"stats_window": 30, "input_format": "common", "csv_columns": {"date": 1}}"#,
+    );
+    let yaml = write(
+        "config.yaml",
+        "stats_window: 30\ninput_format: common\ncsv_columns:\n  date: 1\n",
+    );
+    assert_eq!(Config::load(&toml)?, expected);
+    assert_eq!(Config::load(&json)?, expected);
+    assert_eq!(Config::load(&yaml)?, expected);
+
+    // Errors name the key that couldn't be loaded.
+    let invalid = write(
+        "invalid.json",
+        r#"{"exporters": {"statsd": {"address": "127.0.0.1:8125", "prefix": 1}}}"#,
+    );
+    let error = format!("{:#}", Config::load(&invalid).unwrap_err());
+    assert!(error.contains("`exporters.statsd.prefix`"), "{}", error);
+    let unknown = write("unknown.yaml", "stats_windw: 30\n");
+    let error = format!("{:#}", Config::load(&unknown).unwrap_err());
+    assert!(error.contains("unknown field `stats_windw`"), "{}", error);
+    assert!(Config::load(write("config.ini", "")).is_err());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_validate_config() -> anyhow::Result<()> {
+    assert_eq!(Config::default().validate(), Ok(()));
+
+    let mut config = Config {
+        stats_window: 0,
+        alert_rate: 0,
+        maximum_timestamp_error: u32::MAX,
+        ..Config::default()
+    };
+    config.sections.depth = 0;
+    config.sections.rules.push(PathRule {
+        pattern: "^/users/(".to_string(),
+        replacement: "/users/{id}".to_string(),
+    });
+    config
+        .notifiers
+        .webhooks
+        .push(WebhookConfig::new("hooks.example.com"));
+    config.notifiers.webhooks[0].delivery.queue_capacity = 0;
+
+    let error = config.validate().unwrap_err();
+    let keys: Vec<&str> = error
+        .fields
+        .iter()
+        .map(|field| field.key.as_str())
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "stats_window",
+            "alert_rate",
+            "sections.depth",
+            "sections.rules[0].pattern",
+            "maximum_timestamp_error",
+            "notifiers.webhooks[0].url",
+            "notifiers.webhooks[0].delivery.queue_capacity",
+        ]
+    );
+    assert!(error
+        .to_string()
+        .starts_with("invalid config:\n  stats_window must be at least 1 second\n"));
+
+    // The config is checked before any input is read.
+    let mut source = Cursor::new("");
+    let mut sink = Cursor::new(Vec::new());
+    let result = monitor_stream(&mut source, &mut sink, &config);
+    match result {
+        Err(Error::Config(result)) => assert_eq!(result, error),
+        other => panic!("expected a config error, got {:?}", other),
+    }
+
+    let error = Config {
+        user_alert_rate: Some(5),
+        ..Config::default()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid config:\n  user_alert_rate requires keep_auth_user, or there are no users to alert on"
+    );
     Ok(())
 }